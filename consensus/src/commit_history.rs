@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use model::{Round, Wave};
+use model::vertex::VertexHash;
+
+/// Running hash over the committed order. Nodes which committed the same leaders hold the same
+/// checkpoint, a different one means their committed orders diverged.
+pub type Checkpoint = [u8; 32];
+
+/// A wave leader committed together with its not yet delivered causal history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitEvent {
+    pub wave: Wave,
+    pub leader: VertexHash,
+    pub round: Round,
+    /// Number of vertices delivered with the leader, the leader included.
+    pub delivered: usize,
+    /// Checkpoint once the vertices delivered with the leader are committed.
+    pub checkpoint: Checkpoint,
+}
+
+/// The last commits of a node, shared between consensus and whoever wants to look at them.
+#[derive(Clone)]
+pub struct CommitHistory {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<CommitEvent>>>,
+}
+
+impl CommitHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    pub fn push(&self, event: CommitEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Checkpoint of the last commit, if any is kept.
+    pub fn last_checkpoint(&self) -> Option<Checkpoint> {
+        self.events.lock().unwrap().back().map(|event| event.checkpoint)
+    }
+
+    /// Up to `n` of the last commits, oldest first.
+    pub fn recent_commits(&self, n: usize) -> Vec<CommitEvent> {
+        let events = self.events.lock().unwrap();
+        events.iter().skip(events.len().saturating_sub(n)).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(wave: Wave) -> CommitEvent {
+        CommitEvent { wave, leader: [wave as u8; 32], round: 4 * wave - 3, delivered: 1, checkpoint: [0; 32] }
+    }
+
+    #[test]
+    fn only_the_last_commits_are_kept_in_order() {
+        let history = CommitHistory::new(3);
+        for wave in 1..=5 {
+            history.push(commit(wave));
+        }
+        let waves: Vec<Wave> = history.recent_commits(10).iter().map(|commit| commit.wave).collect();
+        assert_eq!(waves, vec![3, 4, 5]);
+        assert_eq!(history.recent_commits(2), vec![commit(4), commit(5)]);
+    }
+
+    #[test]
+    fn nothing_is_kept_without_capacity() {
+        let history = CommitHistory::new(0);
+        history.push(commit(1));
+        assert!(history.recent_commits(1).is_empty());
+        assert_eq!(history.last_checkpoint(), None);
+    }
+}
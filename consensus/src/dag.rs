@@ -1,50 +1,109 @@
-use std::collections::{BTreeMap, HashMap};
-use std::fmt::{Display, format, Formatter};
-use std::ops::Add;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
 use model::committee::NodePublicKey;
-use model::Round;
+use model::{GENESIS_ROUND, Round};
 use model::vertex::{Vertex, VertexHash};
 
+/// Where a strong path between two vertices is missing, see `Dag::find_path_break`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathBreak {
+    /// First round above the oldest vertex in which no vertex strongly reaches it. When this is
+    /// the round of the newest vertex, the path is only missing its last edge.
+    pub round: Round,
+    /// Vertices of the round below `round` which still strongly reach the oldest vertex.
+    pub candidates: usize,
+    /// Vertices in `round`.
+    pub round_size: usize,
+}
+
 pub struct Dag {
     pub graph: BTreeMap<Round, HashMap<NodePublicKey, Vertex>>,
     min_quorum: u32,
+    /// Rounds below this one have been garbage collected.
+    gc_round: Round,
+    /// Round and owner of every vertex in the graph, to find a vertex from its hash alone.
+    index: HashMap<VertexHash, (Round, NodePublicKey)>,
+    /// Vertices of a round which are strongly reachable from a vertex, by vertex and round. What
+    /// a vertex reaches only depends on its ancestors, which are in the DAG before the vertex and
+    /// are never replaced, so entries stay valid until their round is garbage collected.
+    strong_reach: RefCell<HashMap<(VertexHash, Round), HashSet<VertexHash>>>,
 }
 
 impl Dag {
     pub fn new(root: Vec<Vertex>, min_quorum: u32) -> Self {
         let genesis = root
             .iter()
+            .filter(|v| v.is_genesis())
             .map(|v| (v.owner(), v.clone()))
             .collect::<HashMap<_, _>>();
+        let index = genesis.values().map(|v| (v.hash(), (v.round(), v.owner()))).collect();
         Dag {
-            graph: [(1, genesis)].iter().cloned().collect(),
+            graph: [(GENESIS_ROUND, genesis)].iter().cloned().collect(),
             min_quorum,
+            gc_round: 0,
+            index,
+            strong_reach: RefCell::default(),
+        }
+    }
+
+    /// Remove all rounds below `round` and return the vertices which were removed.
+    pub fn clean_before_round(&mut self, round: Round) -> Vec<Vertex> {
+        if round <= self.gc_round {
+            return vec![];
+        }
+        let retained = self.graph.split_off(&round);
+        let removed = std::mem::replace(&mut self.graph, retained);
+        self.gc_round = round;
+        let removed: Vec<Vertex> = removed.into_values().flat_map(|vertices| vertices.into_values()).collect();
+        for vertex in &removed {
+            self.index.remove(&vertex.hash());
         }
+        self.strong_reach.get_mut().retain(|(_, reached_round), _| *reached_round >= round);
+        removed
     }
 
-    pub fn insert_vertex(&mut self, vertex: Vertex) {
-        self.graph
-            .entry(vertex.round())
-            .or_insert_with(HashMap::new)
-            .insert(vertex.owner(), vertex);
+    pub fn gc_round(&self) -> Round {
+        self.gc_round
+    }
+
+    /// Insert the vertex unless its owner already has another vertex in the round, in which case
+    /// the owner equivocates: the first vertex is kept and false is returned.
+    pub fn insert_vertex(&mut self, vertex: Vertex) -> bool {
+        let vertices = self.graph.entry(vertex.round()).or_default();
+        if let Some(existing) = vertices.get(&vertex.owner()) {
+            return existing.hash() == vertex.hash();
+        }
+        self.index.insert(vertex.hash(), (vertex.round(), vertex.owner()));
+        vertices.insert(vertex.owner(), vertex);
+        true
     }
 
     pub fn contains_vertices(&self, vertices: &BTreeMap<VertexHash, Round>) -> bool {
         vertices.iter().all(|(vertex_hash, round)| {
-            match self.graph.get(round) {
-                Some(v) => v.values().any(|vertex| vertex.hash() == *vertex_hash),
-                None => false
+            // parents which were garbage collected are not needed anymore
+            if *round < self.gc_round {
+                return true;
             }
+            self.get_vertex(*vertex_hash, round).is_some()
         })
     }
 
     pub fn get_vertices(&self, round: &Round) -> BTreeMap<VertexHash, Round> {
         match self.graph.get(round) {
-            Some(v) => v.iter().map(|(_, v)| { (v.hash(), v.round()) }).collect(),
+            Some(v) => v.values().map(|v| { (v.hash(), v.round()) }).collect(),
             None => BTreeMap::default()
         }
     }
 
+    pub fn vertices_count(&self, round: &Round) -> usize {
+        self.graph.get(round).map_or(0, |v| v.len())
+    }
+
+    pub fn min_quorum(&self) -> u32 {
+        self.min_quorum
+    }
+
     pub fn is_quorum_reached_for_round(&self, round: &Round) -> bool {
         match self.graph.get(round) {
             Some(v) => v.len() as u32 >= self.min_quorum,
@@ -52,65 +111,189 @@ impl Dag {
         }
     }
 
-    pub fn is_linked_with_others_in_round(&self, vertex: &Vertex, round: Round) -> bool {
-        let mut weight = 0;
-        for v in self.graph.get(&round).unwrap().values() {
-            if self.is_strongly_linked(v, vertex) {
-                weight += 1;
-            }
+    /// The round before `round` has a quorum of vertices, or was already garbage collected.
+    /// Round 0 has no previous round to be complete.
+    pub fn is_previous_round_complete(&self, round: Round) -> bool {
+        match round.checked_sub(1) {
+            Some(previous_round) => previous_round < self.gc_round || self.is_quorum_reached_for_round(&previous_round),
+            None => false,
         }
-        weight >= self.min_quorum
+    }
+
+    /// Every round from `from` up to `to` (excluded) has a quorum of vertices or was already
+    /// garbage collected, so a path between these rounds is not missed because of a gap.
+    pub fn are_rounds_complete(&self, from: Round, to: Round) -> bool {
+        (from..to).all(|round| round < self.gc_round || self.is_quorum_reached_for_round(&round))
+    }
+
+    /// Number of vertices of `round` with a strong path to `vertex`.
+    pub fn support_in_round(&self, vertex: &Vertex, round: Round) -> u32 {
+        self.graph.get(&round).map_or(0, |vertices| {
+            vertices.values().filter(|v| self.is_strongly_linked(v, vertex)).count() as u32
+        })
     }
 
     pub fn is_strongly_linked(&self, newest: &Vertex, oldest: &Vertex) -> bool {
-        self.is_linked_internal(newest, oldest, |v: &Vertex| -> BTreeMap<VertexHash, Round> { v.get_strong_parents() })
+        if newest.round() <= oldest.round() {
+            return false;
+        }
+        let key = (newest.hash(), oldest.round());
+        if let Some(reached) = self.strong_reach.borrow().get(&key) {
+            return reached.contains(&oldest.hash());
+        }
+
+        let reached: HashSet<VertexHash> = self.ancestors(newest)
+            .down_to(oldest.round())
+            .filter(|v| v.round() == oldest.round())
+            .map(|v| v.hash())
+            .collect();
+        let linked = reached.contains(&oldest.hash());
+        self.strong_reach.borrow_mut().insert(key, reached);
+        linked
+    }
+
+    /// Explain why `newest` is not strongly linked to `oldest`: starting from `oldest`, follow
+    /// the vertices which strongly reach it round after round, and return the first round where
+    /// none is left. None if the vertices are strongly linked.
+    pub fn find_path_break(&self, newest: &Vertex, oldest: &Vertex) -> Option<PathBreak> {
+        let mut reaching = HashSet::from([oldest.hash()]);
+        for round in oldest.round() + 1..=newest.round() {
+            let next: HashSet<VertexHash> = self.graph.get(&round)
+                .into_iter()
+                .flat_map(|vertices| vertices.values())
+                .filter(|vertex| round < newest.round() || vertex.hash() == newest.hash())
+                .filter(|vertex| vertex.strong_parent_hashes().any(|parent| reaching.contains(parent)))
+                .map(|vertex| vertex.hash())
+                .collect();
+            if next.is_empty() {
+                return Some(PathBreak {
+                    round,
+                    candidates: reaching.len(),
+                    round_size: self.vertices_count(&round),
+                });
+            }
+            reaching = next;
+        }
+        None
     }
 
     pub fn is_linked(&self, newest: &Vertex, oldest: &Vertex) -> bool {
-        self.is_linked_internal(newest, oldest, |v: &Vertex| -> BTreeMap<VertexHash, Round> { v.get_all_parents() })
-    }
-
-    fn is_linked_internal(&self, newest: &Vertex, oldest: &Vertex, get_parents: fn(&Vertex) -> BTreeMap<VertexHash, Round>) -> bool {
-        if newest.round() > oldest.round() {
-            let mut vertex_stack = vec![newest];
-            while !vertex_stack.is_empty() {
-                let vertex = vertex_stack.pop().unwrap();
-                for (parent, round) in get_parents(vertex) {
-                    if parent == oldest.hash() {
-                        return true;
-                    } else if round > oldest.round() {
-                        if let Some(parent_vertex) = self.get_vertex(parent, &round) {
-                            vertex_stack.push(parent_vertex)
-                        }
-                    }
+        newest.round() > oldest.round()
+            && self.causal_history(newest).down_to(oldest.round()).any(|v| v.hash() == oldest.hash())
+    }
+
+    /// Vertices reachable from `vertex` through strong edges only.
+    pub fn ancestors<'a>(&'a self, vertex: &'a Vertex) -> Ancestors<'a> {
+        Ancestors::new(self, vertex, true)
+    }
+
+    /// Vertices reachable from `vertex` through both strong and weak edges.
+    pub fn causal_history<'a>(&'a self, vertex: &'a Vertex) -> Ancestors<'a> {
+        Ancestors::new(self, vertex, false)
+    }
+
+    pub fn get_vertex(&self, vertex_hash: VertexHash, round: &Round) -> Option<&Vertex> {
+        self.get_vertex_by_hash(&vertex_hash).filter(|vertex| vertex.round() == *round)
+    }
+
+    pub fn get_vertex_by_hash(&self, vertex_hash: &VertexHash) -> Option<&Vertex> {
+        let (round, owner) = self.index.get(vertex_hash)?;
+        self.graph.get(round).and_then(|vertices| vertices.get(owner))
+    }
+}
+
+/// Breadth-first walk over the ancestors of a vertex (the vertex itself is not included).
+/// Every ancestor is visited once, and the walk does not go below the garbage collected rounds
+/// or the round set by `down_to`.
+pub struct Ancestors<'a> {
+    dag: &'a Dag,
+    queue: VecDeque<&'a Vertex>,
+    visited: HashSet<VertexHash>,
+    strong_only: bool,
+    floor: Round,
+}
+
+impl<'a> Ancestors<'a> {
+    fn new(dag: &'a Dag, vertex: &'a Vertex, strong_only: bool) -> Self {
+        Self {
+            dag,
+            queue: VecDeque::from(vec![vertex]),
+            visited: HashSet::new(),
+            strong_only,
+            floor: dag.gc_round,
+        }
+        .skip_start()
+    }
+
+    /// Do not go below the given round.
+    pub fn down_to(self, round: Round) -> Self {
+        Self {
+            floor: round.max(self.dag.gc_round),
+            ..self
+        }
+    }
+
+    fn skip_start(mut self) -> Self {
+        if let Some(vertex) = self.queue.pop_front() {
+            self.visited.insert(vertex.hash());
+            self.enqueue_parents(vertex);
+        }
+        self
+    }
+
+    fn enqueue_parents(&mut self, vertex: &'a Vertex) {
+        if self.strong_only {
+            self.enqueue(vertex.strong_parents());
+        } else {
+            self.enqueue(vertex.parents().iter());
+        }
+    }
+
+    fn enqueue(&mut self, parents: impl Iterator<Item = (&'a VertexHash, &'a Round)>) {
+        for (parent, round) in parents {
+            // the floor is checked once the vertex is popped, since `down_to` can be called
+            // after the parents of the starting vertex were queued
+            if self.visited.insert(*parent) {
+                if let Some(parent_vertex) = self.dag.get_vertex(*parent, round) {
+                    self.queue.push_back(parent_vertex);
                 }
             }
         }
-        false
     }
+}
 
-    pub fn get_vertex(&self, vertex_hash: VertexHash, round: &Round) -> Option<&Vertex> {
-        match self.graph.get(round) {
-            Some(v) => v.values().find(|vertex| vertex.hash() == vertex_hash),
-            None => None
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a Vertex;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(vertex) = self.queue.pop_front() {
+            if vertex.round() < self.floor {
+                continue;
+            }
+            self.enqueue_parents(vertex);
+            return Some(vertex);
         }
+        None
     }
 }
 
+/// One line per round listing its vertices, numbered within the round, with their parents as
+/// `round-number`. Weak parents are marked with a `~`, parents which were garbage collected are
+/// left out.
 impl Display for Dag {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut vertex_ids = HashMap::new();
         for (r, vertices) in &self.graph {
-            let mut line = format!("{}: ", r.to_string());
+            let mut line = format!("{}: ", r);
 
-            let mut c = 1;
-            for (_, vertex) in vertices {
+            for (c, vertex) in (1..).zip(vertices.values()) {
                 vertex_ids.insert(vertex.hash(), c);
 
                 let mut parents_line = String::new();
                 for (hash, round) in vertex.parents() {
                     if let Some(id) = vertex_ids.get(hash) {
-                        parents_line.push_str(format!(" {}-{}", round, id).as_str());
+                        let kind = if vertex.is_weak_parent(hash) { "~" } else { "" };
+                        parents_line.push_str(format!(" {}{}-{}", kind, round, id).as_str());
                     }
                 }
 
@@ -122,11 +305,9 @@ impl Display for Dag {
                 if c < vertices.len() {
                     line.push_str(" --- ");
                 }
-
-                c += 1;
             }
-            line.push_str("\n");
-            write!(f, "{}", line);
+            line.push('\n');
+            write!(f, "{}", line)?;
         }
         Ok(())
     }
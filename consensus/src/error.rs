@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+use model::committee::Id;
+use model::Round;
+
+#[derive(Debug, Error)]
+pub enum ConsensusError {
+    #[error("Not enough parents to propose in round {round}: {parents} of {quorum} vertices")]
+    NotEnoughParents { round: Round, parents: usize, quorum: u32 },
+
+    #[error("Node {0} is not in the committee")]
+    UnknownNode(Id),
+}
+
+/// Something the DAG or the commit state should never hold, see `State::check_invariants`.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvariantViolation {
+    #[error("Vertex {vertex} is stored under another round or owner than its own")]
+    MisplacedVertex { vertex: String },
+
+    #[error("Vertex {vertex} is beyond the current round {current_round}")]
+    VertexAheadOfRound { vertex: String, current_round: Round },
+
+    #[error("Vertex {vertex} has {parents} strong parents, less than the quorum of {quorum}")]
+    TooFewStrongParents { vertex: String, parents: usize, quorum: u32 },
+
+    #[error("Parent {parent} of the vertex {vertex} is not in an older round")]
+    ParentNotOlder { vertex: String, parent: String },
+
+    #[error("Parent {parent} of the vertex {vertex} is not in the DAG")]
+    MissingParent { vertex: String, parent: String },
+
+    #[error("Round {round} holds vertices while the round before it has no quorum")]
+    IncompleteRound { round: Round },
+
+    #[error("Delivered vertex {0} is not in the DAG")]
+    UnknownDeliveredVertex(String),
+}
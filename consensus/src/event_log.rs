@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use model::block::Block;
+use model::vertex::Vertex;
+
+/// Something the consensus reacts to.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ConsensusInput {
+    Vertex(Vertex),
+    Block(Block),
+    Heartbeat,
+}
+
+/// An input as it was handled by the consensus.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoggedInput {
+    /// Time since the log was created (in ms).
+    pub elapsed: u64,
+    /// Whether block production was paused when the input was handled.
+    pub paused: bool,
+    pub input: ConsensusInput,
+}
+
+/// Records every input of the consensus to a file, in the order they are handled, so that a run
+/// can be reproduced with `Consensus::replay`. Inputs are written one after the other with
+/// bincode and flushed right away, so the log is complete up to a crash.
+pub struct EventLog {
+    writer: BufWriter<File>,
+    started_at: Instant,
+}
+
+impl EventLog {
+    /// Start a new log, replacing the file if it exists.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, paused: bool, input: &ConsensusInput) -> io::Result<()> {
+        let logged = LoggedInput {
+            elapsed: self.started_at.elapsed().as_millis() as u64,
+            paused,
+            input: input.clone(),
+        };
+        bincode::serialize_into(&mut self.writer, &logged).map_err(io::Error::other)?;
+        self.writer.flush()
+    }
+
+    /// Read back the inputs of a log. An input cut short at the end of the file, by a crash
+    /// while it was written, is left out.
+    pub fn read(path: &Path) -> io::Result<Vec<LoggedInput>> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut inputs = Vec::new();
+        loop {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(input) => inputs.push(input),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                    _ => return Err(io::Error::new(ErrorKind::InvalidData, e)),
+                },
+            }
+        }
+        Ok(inputs)
+    }
+}
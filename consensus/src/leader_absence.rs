@@ -0,0 +1,13 @@
+use model::{Round, Wave};
+use model::committee::NodePublicKey;
+
+/// The elected leader of a wave had no vertex in the leader round when the wave ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LeaderAbsence {
+    pub wave: Wave,
+    pub round: Round,
+    /// The validator which was elected leader.
+    pub owner: NodePublicKey,
+    /// Number of waves this validator was missing as leader so far, this one included.
+    pub absences: u64,
+}
@@ -1,134 +1,425 @@
-use std::collections::HashSet;
-use log::{debug, info};
-use tokio::sync::mpsc::{Receiver, Sender};
+use std::collections::{HashMap, VecDeque};
 
-use model::{Round, Wave};
-use model::block::Block;
-use model::committee::{Committee, Id};
-use model::vertex::{Vertex, VertexHash};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{sleep, Duration, Instant};
 
+use model::{GENESIS_ROUND, Round, Wave};
+use model::block::{Block, Transaction};
+use model::committee::{Committee, Id, LeaderSchedule, LeaderSeed, NodePublicKey};
+use model::control::ProductionSwitch;
+use model::encoding::encode_hash;
+use model::genesis::GenesisConfig;
+use model::parameters::Parameters;
+use model::vertex::Vertex;
+use model::wave::WAVE;
+
+use crate::commit_history::{Checkpoint, CommitEvent, CommitHistory};
+use crate::error::ConsensusError;
+use crate::event_log::{ConsensusInput, EventLog, LoggedInput};
+use crate::leader_absence::LeaderAbsence;
+use crate::missing_rounds::MissingRounds;
 use crate::state::State;
+use crate::validator_stats::ValidatorStats;
+
+pub mod commit_history;
+pub mod dag;
+pub mod error;
+pub mod event_log;
+pub mod leader_absence;
+pub mod missing_rounds;
+pub mod state;
+pub mod validator_stats;
 
-mod dag;
-mod state;
+#[cfg(test)]
+mod tests;
+
+/// Channels between the consensus and the rest of the node.
+pub struct ConsensusChannels {
+    /// Vertices received from the other nodes, and the own ones once broadcast.
+    pub vertex_receiver: Receiver<Vertex>,
+    /// Blocks to propose in the next vertices of the node.
+    pub blocks_receiver: Receiver<Block>,
+    /// Vertices proposed by the node, to broadcast.
+    pub vertex_to_broadcast_sender: Sender<Vertex>,
+    /// Committed vertices, in commit order.
+    pub vertex_output_sender: Sender<Vertex>,
+}
 
-const MAX_WAVE: Wave = 4;
+/// Optional outputs of the consensus, each one is only produced when it is set. The default
+/// produces none of them.
+#[derive(Default)]
+pub struct ConsensusOutputs {
+    /// Transactions of the committed vertices, in commit order.
+    pub transaction_output_sender: Option<Sender<Transaction>>,
+    /// Committed vertices once `finality_depth` more leaders were committed on top of them.
+    pub finalized_output_sender: Option<Sender<Vertex>>,
+    /// Told about every wave which ends without a vertex of its elected leader.
+    pub leader_absence_sender: Option<Sender<LeaderAbsence>>,
+    /// Told the rounds a wave waits for before its leader can be committed, to request them from
+    /// the other nodes.
+    pub missing_rounds_sender: Option<Sender<MissingRounds>>,
+    /// Told the new GC round after every garbage collection.
+    pub gc_sender: Option<broadcast::Sender<Round>>,
+    /// Where the last commits are kept for inspection.
+    pub commit_history: Option<CommitHistory>,
+    /// Where the inputs are recorded, to replay the run later.
+    pub event_log: Option<EventLog>,
+}
 
 pub struct Consensus {
     node_id: Id,
     committee: Committee,
+    /// Leader election by the stakes of the genesis config.
+    leader_schedule: LeaderSchedule,
+    parameters: Parameters,
     decided_wave: Wave,
+    /// Round of the last committed leader.
+    last_committed_round: Round,
+    /// Committed leaders whose causal history is not ordered yet, the oldest one last.
+    leaders_to_order: Vec<Vertex>,
+    /// Whether proposals are slowed down because commits lag behind.
+    throttled: bool,
     state: State,
-    delivered_vertices: HashSet<VertexHash>,
-    buffer: Vec<Vertex>,
     blocks_to_propose: Vec<Block>,
     blocks_receiver: Receiver<Block>,
     vertex_receiver: Receiver<Vertex>,
     vertex_output_sender: Sender<Vertex>,
+    /// Transactions of the committed vertices, in commit order, for consumers which do not care
+    /// about the vertices.
+    transaction_output_sender: Option<Sender<Transaction>>,
+    /// Committed vertices once `finality_depth` more leaders were committed on top of them.
+    finalized_output_sender: Option<Sender<Vertex>>,
+    /// Vertices committed by each of the last leaders, oldest leader first, which are not
+    /// finalized yet. Only kept when there is a finalized output.
+    unfinalized: VecDeque<Vec<Vertex>>,
     vertex_to_broadcast_sender: Sender<Vertex>,
+    round_sender: watch::Sender<Round>,
+    /// When the node moved to the current round.
+    round_started_at: Instant,
+    /// When a missing quorum was last reported for the current round.
+    stall_reported_at: Instant,
+    /// Number of times a quorum stall was reported.
+    quorum_stalls: u64,
+    /// While paused the node follows the rounds of the others without proposing vertices.
+    switch: ProductionSwitch,
+    /// The last commits, for inspection from outside.
+    commit_history: CommitHistory,
+    /// Hash chain over the committed order: the previous checkpoint followed by the hashes of
+    /// the vertices output for a leader, after every leader.
+    checkpoint: Checkpoint,
+    /// Told about every wave which ends without a vertex of its elected leader.
+    leader_absence_sender: Option<Sender<LeaderAbsence>>,
+    /// Number of waves each validator was missing as leader.
+    leader_absences: HashMap<NodePublicKey, u64>,
+    /// Told the rounds a wave waits for before its leader can be committed.
+    missing_rounds_sender: Option<Sender<MissingRounds>>,
+    /// Told the new GC round after every garbage collection, so that an application can prune
+    /// its own state; subscribe with `broadcast::Sender::subscribe`. Rounds only increase and are
+    /// sent once. A subscriber which falls behind gets `RecvError::Lagged` and misses the oldest
+    /// rounds, which is harmless since a GC round covers the rounds before it.
+    gc_sender: Option<broadcast::Sender<Round>>,
+    /// Where the inputs are recorded, to replay the run later.
+    event_log: Option<EventLog>,
 }
 
 impl Consensus {
     pub fn spawn(
         node_id: Id,
         committee: Committee,
-        vertex_receiver: Receiver<Vertex>,
-        vertex_to_broadcast_sender: Sender<Vertex>,
-        vertex_output_sender: Sender<Vertex>,
-        blocks_receiver: Receiver<Block>,
-    ) {
+        parameters: Parameters,
+        genesis: GenesisConfig,
+        channels: ConsensusChannels,
+        switch: ProductionSwitch,
+        outputs: ConsensusOutputs,
+    ) -> watch::Receiver<Round> {
+        let (mut consensus, round_receiver) =
+            Self::new(node_id, committee, parameters, genesis, channels, switch, outputs);
         tokio::spawn(async move {
-            let state = State::new(Vertex::genesis(committee.get_nodes_keys()));
-            Self {
-                node_id,
-                committee,
-                vertex_receiver,
-                vertex_output_sender,
-                vertex_to_broadcast_sender,
-                decided_wave: 0,
-                state,
-                delivered_vertices: HashSet::new(),
-                buffer: vec![],
-                blocks_to_propose: vec![],
-                blocks_receiver,
-            }.run().await;
+            consensus.run().await;
         });
+        round_receiver
+    }
+
+    /// Feed the inputs recorded by an `EventLog` through a fresh consensus of the node, the way
+    /// `run` handles them, and return the vertices it commits in order. What the consensus does
+    /// only depends on its inputs and their order, so the recorded run committed the same ones.
+    pub async fn replay(
+        node_id: Id,
+        committee: Committee,
+        parameters: Parameters,
+        genesis: GenesisConfig,
+        inputs: Vec<LoggedInput>,
+    ) -> Vec<Vertex> {
+        let capacities = &parameters.channel_capacities;
+        let (vertex_output_sender, mut vertex_output_receiver) = channel(capacities.output);
+        let (vertex_to_broadcast_sender, mut vertex_to_broadcast_receiver) = channel(capacities.vertices);
+        // the received vertices and blocks come from the log instead
+        let (_vertex_sender, vertex_receiver) = channel(1);
+        let (_block_sender, blocks_receiver) = channel(1);
+
+        let committed = tokio::spawn(async move {
+            let mut committed = Vec::new();
+            while let Some(vertex) = vertex_output_receiver.recv().await {
+                committed.push(vertex);
+            }
+            committed
+        });
+        // the proposed vertices are in the log as well, once they came back from the network
+        tokio::spawn(async move {
+            while vertex_to_broadcast_receiver.recv().await.is_some() {}
+        });
+
+        let channels = ConsensusChannels { vertex_receiver, blocks_receiver, vertex_to_broadcast_sender, vertex_output_sender };
+        let (mut consensus, _) = Self::new(
+            node_id,
+            committee,
+            parameters,
+            genesis,
+            channels,
+            ProductionSwitch::default(),
+            ConsensusOutputs::default(),
+        );
+        for logged in inputs {
+            consensus.handle_input(logged.input, logged.paused).await;
+        }
+        // closes the output, which ends the collection
+        drop(consensus);
+        committed.await.expect("Failed to collect the committed vertices")
+    }
+
+    fn new(
+        node_id: Id,
+        committee: Committee,
+        parameters: Parameters,
+        genesis: GenesisConfig,
+        channels: ConsensusChannels,
+        switch: ProductionSwitch,
+        outputs: ConsensusOutputs,
+    ) -> (Self, watch::Receiver<Round>) {
+        let (round_sender, round_receiver) = watch::channel(GENESIS_ROUND);
+        let state = State::new(Vertex::genesis(&genesis));
+        let consensus = Self {
+            node_id,
+            committee,
+            leader_schedule: LeaderSchedule::new(genesis.validators),
+            parameters,
+            vertex_receiver: channels.vertex_receiver,
+            vertex_output_sender: channels.vertex_output_sender,
+            transaction_output_sender: outputs.transaction_output_sender,
+            finalized_output_sender: outputs.finalized_output_sender,
+            unfinalized: VecDeque::new(),
+            vertex_to_broadcast_sender: channels.vertex_to_broadcast_sender,
+            decided_wave: 0,
+            last_committed_round: GENESIS_ROUND,
+            leaders_to_order: vec![],
+            throttled: false,
+            state,
+            blocks_to_propose: vec![],
+            blocks_receiver: channels.blocks_receiver,
+            round_sender,
+            round_started_at: Instant::now(),
+            stall_reported_at: Instant::now(),
+            quorum_stalls: 0,
+            switch,
+            commit_history: outputs.commit_history.unwrap_or_else(|| CommitHistory::new(0)),
+            checkpoint: Checkpoint::default(),
+            leader_absence_sender: outputs.leader_absence_sender,
+            leader_absences: HashMap::new(),
+            missing_rounds_sender: outputs.missing_rounds_sender,
+            gc_sender: outputs.gc_sender,
+            event_log: outputs.event_log,
+        };
+        (consensus, round_receiver)
     }
 
     async fn run(&mut self) {
+        let heartbeat_delay = Duration::from_millis(self.parameters.heartbeat_delay);
+        let timer = sleep(heartbeat_delay);
+        tokio::pin!(timer);
+
         loop {
-            tokio::select! {
-                Some(vertex) = self.vertex_receiver.recv() => {
-                    debug!("Vertex received in consensus of 'node {}': {}", self.node_id, vertex);
-                    self.buffer.push(vertex);
-
-                    // Go through buffer and add vertex in the dag which meets the requirements
-                    // and remove from the buffer those added
-                    self.buffer.retain(|v| {
-                        if v.round() <= self.state.current_round && self.state.dag.contains_vertices(v.parents()) {
-                        // if v.round() <= self.state.current_round {
-                            self.state.dag.insert_vertex(v.clone());
-                            false
-                        } else {
-                            true
-                        }
-                    })
-                },
-                Some(block) = self.blocks_receiver.recv() => {
-                    self.blocks_to_propose.push(block)
+            let input = tokio::select! {
+                Some(vertex) = self.vertex_receiver.recv() => ConsensusInput::Vertex(vertex),
+                Some(block) = self.blocks_receiver.recv() => ConsensusInput::Block(block),
+                () = &mut timer => {
+                    timer.as_mut().reset(Instant::now() + heartbeat_delay);
+                    ConsensusInput::Heartbeat
                 }
+            };
+            let paused = self.switch.is_paused();
+            if let Some(log) = &mut self.event_log {
+                if let Err(e) = log.record(paused, &input) {
+                    warn!("Failed to record the input in the event log: {}", e);
+                }
+            }
+            if self.handle_input(input, paused).await {
+                timer.as_mut().reset(Instant::now() + heartbeat_delay);
             }
 
-            debug!("Consensus goes to the next iteration");
+            // Nothing above awaits when the channels are ready, so without this point the loop
+            // could keep the executor busy as long as vertices keep coming.
+            tokio::task::yield_now().await;
+        }
+    }
+
+    /// Handle one input, and move to the next round if the current one is complete. Returns
+    /// whether a new vertex was proposed.
+    async fn handle_input(&mut self, input: ConsensusInput, paused: bool) -> bool {
+        let mut heartbeat = false;
+        match input {
+            ConsensusInput::Vertex(vertex) => {
+                debug!("Vertex received in consensus of 'node {}': {}", self.node_id, vertex);
+                self.state.add_vertex(vertex);
+                #[cfg(feature = "check-invariants")]
+                if let Err(violations) = self.state.check_invariants() {
+                    for violation in violations {
+                        error!("DAG invariant violated: {}", violation);
+                    }
+                }
+            }
+            ConsensusInput::Block(block) => self.blocks_to_propose.push(block),
+            ConsensusInput::Heartbeat => {
+                heartbeat = true;
+                self.check_quorum_stall();
+            }
+        }
 
-            if !self.blocks_to_propose.is_empty() && self.state.dag.is_quorum_reached_for_round(&(self.state.current_round)) {
-                info!("DAG has reached the quorum for the round {:?}", self.state.current_round);
-                if Self::is_last_round_in_wave(self.state.current_round) {
-                    info!("Finished the last round {:?} in the wave. Start to order vertices", self.state.current_round);
-                    let ordered_vertices = self.get_ordered_vertices(self.state.current_round / MAX_WAVE);
+        debug!("Consensus goes to the next iteration");
 
-                    info!("Got {} vertices to order", ordered_vertices.len());
-                    for vertex in ordered_vertices {
-                        self.vertex_output_sender
-                            .send(vertex.clone())
-                            .await
-                            .expect("Failed to output vertex");
+        let mut proposed = false;
+        // Without blocks the DAG would stop growing, so on heartbeat propose an empty vertex
+        // if it is needed to get a pending leader committed. While commits lag behind, blocks
+        // wait for the heartbeat as well.
+        let has_blocks = !self.blocks_to_propose.is_empty() && (heartbeat || !self.is_commit_lagging());
+        let has_payload = has_blocks || (heartbeat && self.has_pending_leader());
+        if self.can_advance(has_payload, paused) {
+            self.advance_round();
+            if paused {
+                info!("Block production is paused, no vertex is proposed in the round {}", self.state.current_round);
+            } else {
+                match self.create_new_vertex(self.state.current_round).await {
+                    Ok(new_vertex) => {
+                        info!("Broadcast the new vertex {}", new_vertex);
+                        self.vertex_to_broadcast_sender.send(new_vertex).await.unwrap();
+                        proposed = true;
                     }
+                    // the node keeps following the others and proposes again in the next round
+                    Err(e) => error!("Failed to create a new vertex: {}", e),
                 }
-                // when quorum for the round reached, then go to the next round
-                self.state.current_round += 1;
-                info!("DAG goes to the next round {:?} \n{}", self.state.current_round, self.state.dag);
-                let new_vertex = self.create_new_vertex(self.state.current_round).await.unwrap();
+            }
+        }
+
+        if !self.leaders_to_order.is_empty() {
+            self.output_committed_vertices().await;
+        }
+        proposed
+    }
+
+    /// Whether the node moves to the next round: the current round has a quorum, and the node has
+    /// something to propose in the next one or is paused and proposes nothing.
+    fn can_advance(&self, has_payload: bool, paused: bool) -> bool {
+        (has_payload || paused) && self.state.dag.is_quorum_reached_for_round(&self.state.current_round)
+    }
 
-                info!("Broadcast the new vertex {}", new_vertex);
-                self.vertex_to_broadcast_sender.send(new_vertex).await.unwrap();
+    /// The current round has a quorum: commit the wave it ends, if any, and go to the next round.
+    fn advance_round(&mut self) {
+        info!("DAG has reached the quorum for the round {:?}", self.state.current_round);
+        // the wave which ends with this round, if any
+        let ended_wave = WAVE
+            .wave_of(self.state.current_round)
+            .filter(|_| WAVE.is_last_round_of_wave(self.state.current_round));
+        if let Some(wave) = ended_wave {
+            self.check_leader_presence(wave);
+        }
+        if let Some(wave) = ended_wave.filter(|wave| self.parameters.commit_rule.is_commit_wave(*wave)) {
+            info!("Finished the last round {:?} in the wave. Start to order vertices", self.state.current_round);
+            self.commit_wave(wave);
+        }
+        // when quorum for the round reached, then go to the next round
+        self.state.current_round += 1;
+        let _ = self.round_sender.send(self.state.current_round);
+        self.round_started_at = Instant::now();
+        self.stall_reported_at = self.round_started_at;
+        info!("DAG goes to the next round {:?} \n{}", self.state.current_round, self.state.dag);
+    }
+
+    /// Order the causal history of the oldest committed leaders, up to `max_leaders_per_iteration`
+    /// of them, and output it. The DAG is then garbage collected up to the ordering floor of the
+    /// next leader, which the leaders left in the queue do not go below either.
+    async fn output_committed_vertices(&mut self) {
+        let ordered_vertices = self.order_next_leaders();
+        for vertex in ordered_vertices {
+            if let Some(sender) = &self.transaction_output_sender {
+                for transaction in &vertex.block().transactions {
+                    sender.send(transaction.clone()).await.expect("Failed to output transaction");
+                }
+            }
+            self.vertex_output_sender
+                .send(vertex.clone())
+                .await
+                .expect("Failed to output vertex");
+        }
+        if let Some(sender) = &self.finalized_output_sender {
+            while self.unfinalized.len() > self.parameters.finality_depth {
+                for vertex in self.unfinalized.pop_front().unwrap_or_default() {
+                    sender.send(vertex).await.expect("Failed to output finalized vertex");
+                }
             }
         }
+
+        self.collect_garbage();
+    }
+
+    fn order_next_leaders(&mut self) -> Vec<Vertex> {
+        let limit = self.parameters.max_leaders_per_iteration.unwrap_or(usize::MAX).max(1);
+        let mut leaders = self.leaders_to_order.split_off(self.leaders_to_order.len().saturating_sub(limit));
+        let ordered_vertices = self.order_vertices(&mut leaders);
+
+        info!("Got {} vertices to order", ordered_vertices.len());
+        if !self.leaders_to_order.is_empty() {
+            info!("{} committed leaders are left to order in the next iterations", self.leaders_to_order.len());
+        }
+        debug!("{} vertices in the DAG are not committed yet", self.state.uncommitted().len());
+        ordered_vertices
     }
 
-    async fn create_new_vertex(&mut self, round: Round) -> Option<Vertex> {
-        let block = self.blocks_to_propose.pop().unwrap();
+    async fn create_new_vertex(&mut self, round: Round) -> Result<Vertex, ConsensusError> {
+        let owner = self.committee.get_node_key(self.node_id).ok_or(ConsensusError::UnknownNode(self.node_id))?;
+        // a round without a previous one has no parents and fails the quorum check below
+        let parents = round.checked_sub(1).map(|previous_round| self.state.dag.get_vertices(&previous_round)).unwrap_or_default();
+        let quorum = self.state.dag.min_quorum();
+        if (parents.len() as u32) < quorum {
+            return Err(ConsensusError::NotEnoughParents { round, parents: parents.len(), quorum });
+        }
+
+        let block = self.blocks_to_propose.pop().unwrap_or_default();
         info!("Start to create a new vertex with the block and {} transactions", block.transactions.len());
-        let parents = self.state.dag.get_vertices(&(round - 1));
         let mut vertex = Vertex::new(
-            self.committee.get_node_key(self.node_id).unwrap(),
+            owner,
             round,
             block,
             parents,
         );
 
-        if round > 2 {
+        // weak edges can only go to rounds below the parents' round
+        if round > GENESIS_ROUND + 1 {
             self.set_weak_edges(&mut vertex, round);
         }
 
-        return Some(vertex);
+        Ok(vertex)
     }
 
     fn set_weak_edges(&self, vertex: &mut Vertex, round: Round) {
-        for r in (1..round - 2).rev() {
+        // only look a bounded number of rounds back, the cost and the vertex size would
+        // otherwise grow with the DAG history
+        let newest_round = round.saturating_sub(2);
+        let oldest_round = newest_round.saturating_sub(self.parameters.weak_edge_lookback).max(GENESIS_ROUND);
+        for r in (oldest_round..newest_round).rev() {
             if let Some(vertices) = self.state.dag.graph.get(&r) {
-                for (_, v) in vertices {
-                    if !self.state.dag.is_linked(&vertex, v) {
+                for v in vertices.values() {
+                    if !self.state.dag.is_linked(vertex, v) {
                         vertex.add_parent(v.hash(), r)
                     }
                 }
@@ -136,48 +427,93 @@ impl Consensus {
         }
     }
 
-    fn get_ordered_vertices(&mut self, wave: Wave) -> Vec<Vertex> {
+    /// Commit the leader of the wave if it has enough support, together with the leaders of the
+    /// previous waves it has a path to. They are queued to be ordered after the leaders committed
+    /// before.
+    fn commit_wave(&mut self, wave: Wave) {
+        // there is no wave 0
+        let round = match WAVE.last_round_of_wave(wave) {
+            Some(round) => round,
+            None => return,
+        };
         if let Some(leader) = self.get_wave_vertex_leader(wave) {
             debug!("Selected a vertex leader: {}", leader);
-            // we need to make sure that if one correct process commits the wave
-            // vertex leader 𝑣, then all the other correct processes will commit 𝑣
-            // later. To this end, we use standard quorum intersection. Process 𝑝𝑖
-            // commits the wave 𝑤 vertex leader 𝑣 if:
-            let round = self.get_round_for_wave(wave, MAX_WAVE);
-            if self.state.dag.is_linked_with_others_in_round(leader, round) {
-                debug!("The leader is strongly linked to others in the round {}", round);
-                let mut leaders_to_commit = self.get_leaders_to_commit(wave - 1, leader);
+            let support = self.state.dag.support_in_round(leader, round);
+            if support >= self.commit_threshold() {
+                debug!("The leader is strongly linked to {} vertices in the round {}", support, round);
+                let mut leaders_to_commit = match self.get_leaders_to_commit(wave, leader) {
+                    Ok(leaders) => leaders,
+                    Err(missing) => return self.request_missing_rounds(missing),
+                };
                 self.decided_wave = wave;
                 debug!("Set decided wave to {}", wave);
 
-                // go through the un-committed leaders starting from the oldest one
-                return self.order_vertices(&mut leaders_to_commit);
+                // the leaders are ordered starting from the oldest one, which is taken from the tail
+                leaders_to_commit.append(&mut self.leaders_to_order);
+                self.leaders_to_order = leaders_to_commit;
+
+                for (owner, stats) in self.validator_stats() {
+                    debug!("Liveness of the validator {}: {:?}", encode_hash(owner), stats);
+                }
             }
         }
-        return vec![];
     }
 
-    fn get_leaders_to_commit(&self, from_wave: Wave, current_leader: &Vertex) -> Vec<Vertex> {
+    /// Support a wave leader needs to be committed: the number of vertices of the last round of
+    /// the wave with a strong path to it. DAG-Rider requires a quorum (2f+1) there, not the f+1
+    /// of the protocols with shorter waves. If one correct node commits the leader, this quorum
+    /// intersects the strong parents of every vertex of the next round, so every later leader
+    /// has a path to it and the other correct nodes commit it as well.
+    fn commit_threshold(&self) -> u32 {
+        self.state.dag.min_quorum()
+    }
+
+    /// The leader of the `wave` followed by the leaders of the previous undecided waves it is
+    /// strongly linked to, newest first. Fails when the rounds between two leaders are not all
+    /// complete, since a path between them could be missing.
+    fn get_leaders_to_commit(&self, wave: Wave, current_leader: &Vertex) -> Result<Vec<Vertex>, MissingRounds> {
         let mut to_commit = vec![current_leader.clone()];
         let mut current_leader = current_leader;
 
-        if from_wave > 0 {
-            // Go for each wave up until decided_wave and find which leaders we need to commit
-            for wave in (from_wave..self.decided_wave + 1).rev()
-            {
-                // Get the vertex proposed in the previous wave.
-                debug!("Get the vertex proposed in the previous wave.");
-                if let Some(prev_leader) = self.get_wave_vertex_leader(wave) {
-                    // if no strong link between leaders then skip for this wave
-                    // and maybe next time there will be a strong link
-                    if self.state.dag.is_strongly_linked(current_leader, prev_leader) {
-                        to_commit.push(prev_leader.clone());
-                        current_leader = prev_leader;
-                    }
+        // Go for each wave down to the one after decided_wave and find which leaders we need to commit
+        for previous_wave in (self.decided_wave + 1..wave).rev() {
+            // Get the vertex proposed in the previous wave.
+            debug!("Get the vertex proposed in the previous wave.");
+            if let Some(prev_leader) = self.get_wave_vertex_leader(previous_wave) {
+                // vertices are only admitted once the round before them has a quorum, so a gap
+                // here should not happen. Skipping the leader could make this node order
+                // differently from the others, so the wave is left for later instead.
+                if !self.state.dag.are_rounds_complete(prev_leader.round(), current_leader.round()) {
+                    return Err(MissingRounds { wave, from: prev_leader.round(), to: current_leader.round() });
+                }
+                // if no strong link between leaders then skip for this wave
+                // and maybe next time there will be a strong link
+                if self.state.dag.is_strongly_linked(current_leader, prev_leader) {
+                    to_commit.push(prev_leader.clone());
+                    current_leader = prev_leader;
+                } else if let Some(path_break) = self.state.dag.find_path_break(current_leader, prev_leader) {
+                    info!(
+                        "Leader of the wave {} is not strongly linked to {}: the path breaks at the round {}, none of its {} \
+                        vertices refers to the {} vertices of the previous round which reach the leader",
+                        previous_wave, current_leader, path_break.round, path_break.round_size, path_break.candidates
+                    );
                 }
             }
         }
-        to_commit
+        Ok(to_commit)
+    }
+
+    /// Report the rounds the wave waits for and ask for them. The wave stays undecided, so its
+    /// leader is committed through the leader of a later wave once the rounds are complete.
+    fn request_missing_rounds(&self, missing: MissingRounds) {
+        error!(
+            "Rounds {} to {} are incomplete while committing the leader of the wave {}, the wave is left undecided",
+            missing.from, missing.to, missing.wave
+        );
+        if let Some(sender) = &self.missing_rounds_sender {
+            // the requests are retried with the next waves, a full channel only delays them
+            let _ = sender.try_send(missing);
+        }
     }
 
     fn order_vertices(&mut self, leaders: &mut Vec<Vertex>) -> Vec<Vertex> {
@@ -186,41 +522,285 @@ impl Consensus {
         // go from the oldest leader to the newest by taking items from the tail
         while let Some(leader) = leaders.pop() {
             debug!("Start ordering vertices from the leader: {:?}", leader);
+            let floor = self.ordering_floor();
+            self.state.committed_leaders.insert(leader.hash());
+            self.last_committed_round = self.last_committed_round.max(leader.round());
 
-            for (round, vertices) in &self.state.dag.graph {
-                if *round > 0 {
-                    for vertex in vertices.values() {
-                        let vertex_hash = vertex.hash();
-                        if !self.delivered_vertices.contains(&vertex_hash) && self.state.dag.is_linked(vertex, &leader) {
-                            ordered_vertices.push(vertex.clone());
-                            self.delivered_vertices.insert(vertex_hash);
-                        }
-                    }
+            // deliver the not yet delivered causal history of the leader above its floor, oldest
+            // rounds first and in the same order on every node
+            let mut to_deliver: Vec<&Vertex> = std::iter::once(&leader)
+                .chain(self.state.dag.causal_history(&leader).down_to(floor))
+                .filter(|v| !self.state.delivered_vertices.contains(&v.hash()))
+                .collect();
+            to_deliver.sort_by_key(|v| (v.round(), v.hash()));
+
+            let delivered = to_deliver.len();
+            let first_delivered = ordered_vertices.len();
+            for vertex in to_deliver {
+                self.state.delivered_vertices.insert(vertex.hash());
+                // the vertex keeps its place in the DAG, but a payload which cannot be
+                // materialized is not delivered
+                if let Err(e) = vertex.block().verify() {
+                    warn!("Skipped vertex {} in the ordering: {}", vertex, e);
+                    continue;
                 }
+                ordered_vertices.push(vertex.clone());
+            }
+            if self.finalized_output_sender.is_some() {
+                self.unfinalized.push_back(ordered_vertices[first_delivered..].to_vec());
+            }
+
+            let mut hasher = blake3::Hasher::new();
+            hasher.update(&self.checkpoint);
+            for vertex in &ordered_vertices[first_delivered..] {
+                hasher.update(&vertex.hash());
             }
+            self.checkpoint = *hasher.finalize().as_bytes();
+            debug!("Checkpoint after the leader {}: {}", leader, encode_hash(self.checkpoint));
+
+            self.commit_history.push(CommitEvent {
+                // leaders come from the DAG, which has no round before the genesis one
+                wave: WAVE.wave_of(leader.round()).unwrap_or_default(),
+                leader: leader.hash(),
+                round: leader.round(),
+                delivered,
+                checkpoint: self.checkpoint,
+            });
         }
 
         ordered_vertices
     }
 
+    /// Owner of the leader vertex of `round`, or None if no leader is elected in this round.
+    /// Leaders are elected in the first round of every wave, in proportion to their stake.
+    pub fn expected_leader(&self, round: Round) -> Option<NodePublicKey> {
+        LeaderSeed::from_round(round).and_then(|seed| self.leader_schedule.leader(seed))
+    }
+
+    /// Liveness of every validator. It is derived from the rounds still in the DAG, so it covers
+    /// a window of recent rounds which moves with the garbage collection.
+    pub fn validator_stats(&self) -> HashMap<NodePublicKey, ValidatorStats> {
+        let mut stats: HashMap<NodePublicKey, ValidatorStats> = self.committee.get_nodes_keys()
+            .into_iter()
+            .map(|key| (key, ValidatorStats::default()))
+            .collect();
+        // the current round is still being filled
+        for round in self.state.dag.gc_round().max(GENESIS_ROUND)..self.state.current_round {
+            let vertices = self.state.dag.graph.get(&round);
+            for (key, validator) in stats.iter_mut() {
+                if vertices.is_some_and(|vertices| vertices.contains_key(key)) {
+                    validator.present += 1;
+                } else {
+                    validator.absent += 1;
+                }
+            }
+
+            // the leaders of the waves which are not decided yet may still be committed
+            if WAVE.wave_of(round).is_none_or(|wave| wave > self.decided_wave) {
+                continue;
+            }
+            if let Some(leader) = self.expected_leader(round) {
+                let committed = vertices
+                    .and_then(|vertices| vertices.get(&leader))
+                    .is_some_and(|vertex| {
+                        self.state.committed_leaders.contains(&vertex.hash())
+                            || self.leaders_to_order.iter().any(|queued| queued.hash() == vertex.hash())
+                    });
+                let validator = stats.entry(leader).or_default();
+                if committed {
+                    validator.leader_committed += 1;
+                } else {
+                    validator.leader_skipped += 1;
+                }
+            }
+        }
+        stats
+    }
+
     fn get_wave_vertex_leader(&self, wave: Wave) -> Option<&Vertex> {
-        let first_round_of_wave = self.get_round_for_wave(wave, 1);
-        let coin = first_round_of_wave;
+        let first_round_of_wave = WAVE.first_round_of_wave(wave)?;
+        let leader = self.expected_leader(first_round_of_wave)?;
 
-        // Elect the leader.
-        let mut keys: Vec<_> = self.committee.get_nodes_keys();
-        keys.sort();
-        let leader = keys[coin as usize % self.committee.size()];
+        self.state.dag.graph.get(&first_round_of_wave)
+            .and_then(|x| x.get(&leader))
+            .filter(|vertex| self.is_eligible_leader(vertex))
+    }
 
-        // leader is elected at the first round of the wave
-        self.state.dag.graph.get(&first_round_of_wave).map(|x| x.get(&leader)).flatten()
+    /// A leader has to build on a quorum of the previous round like any honest vertex, a sparse
+    /// leader vertex is treated as missing. Genesis vertices have no parents to check.
+    fn is_eligible_leader(&self, vertex: &Vertex) -> bool {
+        if vertex.is_genesis() || vertex.strong_parent_count() as u32 >= self.state.dag.min_quorum() {
+            return true;
+        }
+        debug!("Leader vertex {} has only {} strong parents, it cannot be committed", vertex, vertex.strong_parent_count());
+        false
     }
 
-    fn get_round_for_wave(&self, wave: Wave, round: Round) -> Round {
-        (MAX_WAVE * (wave - 1) + round) as Round
+    /// Report when the current round does not get a quorum of vertices for a while, which tells
+    /// a slow round apart from a committee with not enough validators online.
+    fn check_quorum_stall(&mut self) {
+        let round = self.state.current_round;
+        if self.state.dag.is_quorum_reached_for_round(&round) {
+            return;
+        }
+        let stall_delay = Duration::from_millis(self.parameters.quorum_stall_delay);
+        if self.stall_reported_at.elapsed() < stall_delay {
+            return;
+        }
+
+        self.stall_reported_at = Instant::now();
+        self.quorum_stalls += 1;
+        warn!(
+            "Quorum stall #{}: round {} has {} of {} required vertices after {} ms",
+            self.quorum_stalls,
+            round,
+            self.state.dag.vertices_count(&round),
+            self.state.dag.min_quorum(),
+            self.round_started_at.elapsed().as_millis()
+        );
     }
 
-    fn is_last_round_in_wave(round: Round) -> bool {
-        round % MAX_WAVE == 0
+    /// Report when the elected leader of the wave has no vertex in the leader round.
+    fn check_leader_presence(&mut self, wave: Wave) {
+        let round = match WAVE.first_round_of_wave(wave) {
+            Some(round) => round,
+            None => return,
+        };
+        if self.get_wave_vertex_leader(wave).is_some() {
+            return;
+        }
+        let owner = match self.expected_leader(round) {
+            Some(owner) => owner,
+            None => return,
+        };
+        let absences = self.leader_absences.entry(owner).or_default();
+        *absences += 1;
+        warn!("No vertex of the leader {} in the round {} (missing {} times)", encode_hash(owner), round, absences);
+
+        if let Some(sender) = &self.leader_absence_sender {
+            let absence = LeaderAbsence { wave, round, owner, absences: *absences };
+            // observers are not allowed to hold up consensus
+            let _ = sender.try_send(absence);
+        }
+    }
+
+    /// Lowest round from which the causal history of the next leader is delivered: `gc_depth`
+    /// rounds below the previously ordered leader. It only depends on the sequence of ordered
+    /// leaders, which is the same on every node, and not on when the DAG was garbage collected,
+    /// so every node delivers the same history for a leader however the leaders were batched.
+    fn ordering_floor(&self) -> Round {
+        self.last_committed_round.saturating_sub(self.parameters.gc_depth)
+    }
+
+    /// Collect the rounds below the ordering floor, no leader left to order reaches below it.
+    fn collect_garbage(&mut self) {
+        let gc_round = self.ordering_floor();
+        if gc_round > self.state.dag.gc_round() {
+            debug!("Garbage collect rounds before {}", gc_round);
+            let abandoned = self.state.clean_before_round(gc_round);
+            if abandoned > 0 {
+                info!(
+                    "Abandoned {} vertices no committed leader reached before the round {}, {} in total",
+                    abandoned, gc_round, self.state.abandoned()
+                );
+            }
+            if let Some(sender) = &self.gc_sender {
+                // nobody may be subscribed at the moment
+                let _ = sender.send(gc_round);
+            }
+        }
+    }
+
+    /// The node got more than `max_commit_gap` rounds ahead of its last committed leader.
+    fn is_commit_lagging(&mut self) -> bool {
+        let lagging = match self.parameters.max_commit_gap {
+            Some(gap) => self.state.current_round.saturating_sub(self.last_committed_round) > gap,
+            None => false,
+        };
+        if lagging != self.throttled {
+            self.throttled = lagging;
+            match lagging {
+                true => warn!(
+                    "Round {} is more than {} rounds ahead of the last committed leader in the round {}, slow down the proposals",
+                    self.state.current_round, self.parameters.max_commit_gap.unwrap_or_default(), self.last_committed_round
+                ),
+                false => info!("Commits caught up with the round {}, stop slowing down the proposals", self.state.current_round),
+            }
+        }
+        lagging
+    }
+
+    /// Checkpoint of the committed order so far, see `CommitEvent::checkpoint`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint
+    }
+
+    /// Consensus of the node which takes its vertices from `order_offline` instead of the
+    /// network, and does not propose any.
+    fn offline(node_id: Id, committee: Committee, parameters: Parameters, outputs: ConsensusOutputs) -> Self {
+        let genesis = GenesisConfig::from_committee(&committee);
+        // nothing is sent or received through them
+        let (vertex_to_broadcast_sender, _) = channel(1);
+        let (vertex_output_sender, _) = channel(1);
+        let (_, vertex_receiver) = channel(1);
+        let (_, blocks_receiver) = channel(1);
+        let channels = ConsensusChannels { vertex_receiver, blocks_receiver, vertex_to_broadcast_sender, vertex_output_sender };
+        let (consensus, _) =
+            Self::new(node_id, committee, parameters, genesis, channels, ProductionSwitch::default(), outputs);
+        consensus
+    }
+
+    /// See `order_offline`.
+    fn order_offline(&mut self, mut vertices: Vec<Vertex>) -> Vec<Vertex> {
+        vertices.retain(|vertex| vertex.round() > GENESIS_ROUND);
+        vertices.sort_by_key(|vertex| vertex.round());
+        let mut committed = Vec::new();
+        for vertex in vertices {
+            self.state.add_vertex(vertex);
+            committed.extend(self.handle_offline_input());
+        }
+        // what the heartbeats of a node do once no vertex comes anymore
+        while self.can_advance(false, true) || !self.leaders_to_order.is_empty() {
+            committed.extend(self.handle_offline_input());
+        }
+        committed
+    }
+
+    /// The part of `handle_input` which follows a received vertex on a paused node, without the
+    /// outputs: move to the next round if the current one is complete, then order the next
+    /// committed leaders.
+    fn handle_offline_input(&mut self) -> Vec<Vertex> {
+        if self.can_advance(false, true) {
+            self.advance_round();
+        }
+        if self.leaders_to_order.is_empty() {
+            return vec![];
+        }
+        let ordered_vertices = self.order_next_leaders();
+        self.collect_garbage();
+        ordered_vertices
+    }
+
+    fn has_pending_leader(&self) -> bool {
+        let current_wave = WAVE.wave_of(self.state.current_round).unwrap_or_default();
+        (self.decided_wave + 1..=current_wave).any(|wave| self.get_wave_vertex_leader(wave).is_some())
+    }
+}
+
+/// Run the ordering of the node `node_id` over a complete set of vertices, such as the vertices
+/// captured in an event log, and return the vertices it commits in order. There is no network,
+/// task or timer involved: the vertices are taken in round order and handled the way a paused
+/// node handles received vertices, which follows the rounds of the others without proposing
+/// vertices of its own, then commits, orders and garbage collects as it would in a run.
+/// Vertices of the genesis round are built from the committee and left out of the set.
+pub fn order_offline(
+    node_id: Id,
+    vertices: Vec<Vertex>,
+    committee: Committee,
+    parameters: Parameters,
+) -> Result<Vec<Vertex>, ConsensusError> {
+    if committee.get_node_key(node_id).is_none() {
+        return Err(ConsensusError::UnknownNode(node_id));
     }
+    Ok(Consensus::offline(node_id, committee, parameters, ConsensusOutputs::default()).order_offline(vertices))
 }
@@ -0,0 +1,14 @@
+use model::{Round, Wave};
+
+/// Rounds without a quorum of vertices were found between two leaders while deciding a wave.
+/// The wave is left undecided and decided again with a later leader, once the rounds are filled
+/// in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingRounds {
+    /// The wave whose leader was being committed.
+    pub wave: Wave,
+    /// First round of the range with an incomplete round.
+    pub from: Round,
+    /// Round after the last one of the range.
+    pub to: Round,
+}
@@ -1,14 +1,40 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write as _;
 
-use model::Round;
+use log::warn;
+
+use model::{GENESIS_ROUND, Round};
+use model::committee::NodePublicKey;
+use model::encoding::encode_hash;
 use model::vertex::{Vertex, VertexHash};
 
 use crate::dag::Dag;
+use crate::error::InvariantViolation;
+
+/// How many of the latest abandoned vertices are kept for inspection.
+const ABANDONED_CAPACITY: usize = 1_000;
+
+/// A vertex which was garbage collected without ever being delivered: no committed leader
+/// reached it, so its block was discarded.
+#[derive(Clone, Debug)]
+pub struct AbandonedVertex {
+    pub hash: VertexHash,
+    pub owner: NodePublicKey,
+    pub round: Round,
+}
 
 pub struct State {
     pub current_round: Round,
     pub delivered_vertices: HashSet<VertexHash>,
+    /// Wave leaders which were committed and are still in the DAG.
+    pub committed_leaders: HashSet<VertexHash>,
     pub dag: Dag,
+    /// Received vertices which cannot be added to the DAG yet.
+    buffer: Vec<Vertex>,
+    /// Number of vertices abandoned so far.
+    abandoned: u64,
+    /// The latest abandoned vertices, oldest first.
+    recently_abandoned: VecDeque<AbandonedVertex>,
 }
 
 impl State {
@@ -16,18 +42,189 @@ impl State {
         let min_quorum = (2 * genesis_vertices.len() / 3 + 1) as u32;
         let genesis = genesis_vertices.clone()
             .iter()
+            .filter(|x| x.is_genesis())
             .map(|x| (x.owner(), x.clone()))
             .collect::<HashMap<_, _>>();
 
         Self {
-            current_round: 1,
-            delivered_vertices: genesis.iter().map(|(_, v)| v.hash()).collect(),
+            current_round: GENESIS_ROUND,
+            delivered_vertices: genesis.values().map(|v| v.hash()).collect(),
+            committed_leaders: HashSet::new(),
             dag: Dag::new(genesis_vertices.clone(), min_quorum),
+            buffer: vec![],
+            abandoned: 0,
+            recently_abandoned: VecDeque::new(),
+        }
+    }
+
+    /// Take a received vertex and add to the DAG every buffered vertex which meets the
+    /// requirements, this one included. Vertices are admitted in round order: a vertex waits
+    /// until the round before its own has a quorum, and sorting the buffer lets a vertex admitted
+    /// in this pass complete the quorum of the next round.
+    pub fn add_vertex(&mut self, vertex: Vertex) {
+        self.buffer.push(vertex);
+        self.buffer.sort_by_key(|v| v.round());
+
+        let dag = &mut self.dag;
+        let current_round = self.current_round;
+        self.buffer.retain(|v| {
+            if v.round() < dag.gc_round() {
+                // too late, its round is already garbage collected
+                false
+            } else if v.round() <= GENESIS_ROUND {
+                // genesis is built locally, nobody proposes vertices for it
+                warn!("Dropped vertex {} claiming the genesis round", v);
+                false
+            } else if !v.is_round_consistent() {
+                // a vertex claiming a later round than its parents would count in the quorum and
+                // leader of a round it was not built in
+                warn!("Dropped vertex {} whose round does not follow the rounds of its parents", v);
+                false
+            } else if v.round() <= current_round
+                && dag.is_previous_round_complete(v.round())
+                && dag.contains_vertices(v.parents()) {
+                if !dag.insert_vertex(v.clone()) {
+                    warn!("Owner of the vertex {} equivocates, kept its first vertex of the round", v);
+                }
+                false
+            } else {
+                true
+            }
+        })
+    }
+
+    /// Number of received vertices waiting to be added to the DAG.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Garbage collect rounds below `round`, forgetting the delivered vertices of those rounds
+    /// as well so that the delivered set does not outgrow the DAG. Collected vertices which were
+    /// never delivered are counted as abandoned, and their number is returned.
+    pub fn clean_before_round(&mut self, round: Round) -> usize {
+        let mut abandoned = 0;
+        for vertex in self.dag.clean_before_round(round) {
+            self.committed_leaders.remove(&vertex.hash());
+            if !self.delivered_vertices.remove(&vertex.hash()) {
+                abandoned += 1;
+                if self.recently_abandoned.len() == ABANDONED_CAPACITY {
+                    self.recently_abandoned.pop_front();
+                }
+                self.recently_abandoned.push_back(AbandonedVertex {
+                    hash: vertex.hash(),
+                    owner: vertex.owner(),
+                    round: vertex.round(),
+                });
+            }
         }
+        self.abandoned += abandoned as u64;
+        abandoned
+    }
+
+    /// Number of vertices garbage collected so far without ever being delivered. A growing
+    /// number points to contention between vertices or to byzantine owners.
+    pub fn abandoned(&self) -> u64 {
+        self.abandoned
     }
 
-    pub fn set_vertex_as_delivered(&mut self, vertex_hash: VertexHash) {
-        self.delivered_vertices.insert(vertex_hash);
+    /// The latest abandoned vertices, oldest first.
+    pub fn recently_abandoned(&self) -> impl Iterator<Item=&AbandonedVertex> {
+        self.recently_abandoned.iter()
     }
 
+    /// Vertices of the resident rounds which were not delivered yet, oldest rounds first.
+    pub fn uncommitted(&self) -> Vec<&Vertex> {
+        self.dag.graph
+            .values()
+            .flat_map(|vertices| vertices.values())
+            .filter(|vertex| !self.delivered_vertices.contains(&vertex.hash()))
+            .collect()
+    }
+
+    /// Check the whole DAG and commit state for corruption. This walks every vertex, so it is
+    /// meant for debugging and testing rather than for every round of a running node.
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = Vec::new();
+        let gc_round = self.dag.gc_round();
+        for (round, vertices) in &self.dag.graph {
+            // vertices are admitted once the round before them has a quorum, a gap below them
+            // could hide a path between two leaders
+            if *round > GENESIS_ROUND && !self.dag.is_previous_round_complete(*round) {
+                violations.push(InvariantViolation::IncompleteRound { round: round - 1 });
+            }
+            for (owner, vertex) in vertices {
+                let hash = encode_hash(vertex.hash());
+                // the DAG keeps one vertex per owner and round, so a vertex stored under another
+                // key hides an equivocation or a wrong round
+                if vertex.round() != *round || vertex.owner() != *owner {
+                    violations.push(InvariantViolation::MisplacedVertex { vertex: hash.clone() });
+                }
+                if vertex.round() > self.current_round {
+                    violations.push(InvariantViolation::VertexAheadOfRound {
+                        vertex: hash.clone(),
+                        current_round: self.current_round,
+                    });
+                }
+                if !vertex.is_genesis() && (vertex.strong_parent_count() as u32) < self.dag.min_quorum() {
+                    violations.push(InvariantViolation::TooFewStrongParents {
+                        vertex: hash.clone(),
+                        parents: vertex.strong_parent_count(),
+                        quorum: self.dag.min_quorum(),
+                    });
+                }
+                for (parent, parent_round) in vertex.parents() {
+                    let parent_hash = encode_hash(parent);
+                    if *parent_round >= vertex.round() {
+                        violations.push(InvariantViolation::ParentNotOlder { vertex: hash.clone(), parent: parent_hash });
+                    } else if *parent_round >= gc_round && self.dag.get_vertex(*parent, parent_round).is_none() {
+                        violations.push(InvariantViolation::MissingParent { vertex: hash.clone(), parent: parent_hash });
+                    }
+                }
+            }
+        }
+        for delivered in &self.delivered_vertices {
+            if self.dag.get_vertex_by_hash(delivered).is_none() {
+                violations.push(InvariantViolation::UnknownDeliveredVertex(encode_hash(delivered)));
+            }
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(violations),
+        }
+    }
+
+    pub fn get_vertex_by_hash(&self, vertex_hash: &VertexHash) -> Option<&Vertex> {
+        self.dag.get_vertex_by_hash(vertex_hash)
+    }
+
+    /// Render the DAG in Graphviz DOT format. Strong edges are solid and weak edges dashed,
+    /// committed leaders are drawn bold and delivered vertices filled.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph dag {\n    rankdir=BT;\n    node [shape=box];\n");
+        for (round, vertices) in &self.dag.graph {
+            for vertex in vertices.values() {
+                let hash = vertex.encoded_hash();
+                let mut attributes = format!("label=\"{} {}\"", round, vertex.short_encoded_owner());
+                if self.delivered_vertices.contains(&vertex.hash()) {
+                    attributes.push_str(", style=filled, fillcolor=lightgrey");
+                }
+                if self.committed_leaders.contains(&vertex.hash()) {
+                    attributes.push_str(", penwidth=3");
+                }
+                let _ = writeln!(dot, "    \"{}\" [{}];", hash, attributes);
+
+                for (parent, parent_round) in vertex.parents() {
+                    // leave out edges to garbage collected vertices
+                    if self.dag.get_vertex(*parent, parent_round).is_none() {
+                        continue;
+                    }
+                    let style = if vertex.is_weak_parent(parent) { " [style=dashed]" } else { "" };
+                    let _ = writeln!(dot, "    \"{}\" -> \"{}\"{};", hash, encode_hash(parent), style);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
\ No newline at end of file
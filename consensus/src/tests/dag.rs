@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+
+use model::vertex::{Vertex, VertexHash};
+
+use crate::state::State;
+use crate::tests::dag_builder::DagBuilder;
+
+fn hash_set<'a>(vertices: impl Iterator<Item = &'a Vertex>) -> HashSet<VertexHash> {
+    vertices.map(|vertex| vertex.hash()).collect()
+}
+
+/// The vertex of the owner 0 in the round 4 strongly reaches its own vertex of the round 3, whose
+/// strong parents are the vertices of the owners 0 and 1 in the round 2, and weakly reaches the
+/// vertex of the owner 2 in the round 2.
+fn dag_with_a_narrow_history() -> DagBuilder {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=2);
+    dag.add(0, 3, &[0, 1]);
+    for owner in 1..4 {
+        dag.add(owner, 3, &[0, 1, 2, 3]);
+    }
+    dag.add(0, 4, &[0]);
+    dag.add_weak_edge(0, 4, 2, 2);
+    dag
+}
+
+fn state_of(dag: &DagBuilder) -> State {
+    let mut state = State::new(dag.genesis());
+    for vertex in dag.vertices() {
+        state.dag.insert_vertex(vertex);
+    }
+    state
+}
+
+#[test]
+fn ancestors_follow_the_strong_edges() {
+    let dag = dag_with_a_narrow_history();
+    let state = state_of(&dag);
+    let newest = dag.get(0, 4);
+
+    let genesis = hash_set(dag.genesis().iter());
+    let mut expected = hash_set([dag.get(0, 3), dag.get(0, 2), dag.get(1, 2)].into_iter());
+    assert_eq!(hash_set(state.dag.ancestors(newest).down_to(2)), expected);
+    expected.extend(genesis.iter());
+    assert_eq!(hash_set(state.dag.ancestors(newest)), expected);
+
+    // the weak edge only adds its vertex, whose parents are already reached
+    expected.insert(dag.get(2, 2).hash());
+    assert_eq!(hash_set(state.dag.causal_history(newest)), expected);
+}
+
+#[test]
+fn cached_strong_links_match_the_ancestors() {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=2);
+    dag.add(0, 3, &[0, 1, 2]);
+    dag.add(1, 3, &[1, 2, 3]);
+    dag.add(2, 3, &[0, 2, 3]);
+    dag.add(3, 3, &[0, 1, 3]);
+    dag.add_rounds(4..=5);
+    let mut state = state_of(&dag);
+    let vertices = dag.vertices();
+
+    for newest in &vertices {
+        let reached = hash_set(state.dag.ancestors(newest));
+        for oldest in &vertices {
+            let linked = reached.contains(&oldest.hash());
+            // the second query is answered from the cache
+            assert_eq!(state.dag.is_strongly_linked(newest, oldest), linked);
+            assert_eq!(state.dag.is_strongly_linked(newest, oldest), linked);
+        }
+    }
+
+    let (newest, oldest) = (dag.get(0, 5), dag.get(1, 2));
+    assert!(state.dag.is_strongly_linked(newest, oldest));
+    state.dag.clean_before_round(3);
+    assert!(!state.dag.is_strongly_linked(newest, oldest));
+}
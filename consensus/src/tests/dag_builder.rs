@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use model::{GENESIS_ROUND, Round};
+use model::block::Block;
+use model::committee::{Committee, LeaderSeed, NodePublicKey};
+use model::genesis::GenesisConfig;
+use model::vertex::{Vertex, VertexHash};
+
+/// Builds the vertices of a DAG round by round for the default committee. Owners are referred to
+/// by their index among the sorted validator keys.
+pub struct DagBuilder {
+    pub committee: Committee,
+    pub owners: Vec<NodePublicKey>,
+    rounds: BTreeMap<Round, BTreeMap<usize, Vertex>>,
+}
+
+impl DagBuilder {
+    pub fn new() -> Self {
+        let committee = Committee::default();
+        let mut owners = committee.get_nodes_keys();
+        owners.sort();
+        let genesis = Vertex::genesis(&GenesisConfig::from_committee(&committee))
+            .into_iter()
+            .map(|vertex| (owners.iter().position(|owner| *owner == vertex.owner()).unwrap(), vertex))
+            .collect();
+        Self {
+            committee,
+            owners,
+            rounds: [(GENESIS_ROUND, genesis)].into_iter().collect(),
+        }
+    }
+
+    /// Add the vertices of every owner in the rounds, each one with all the vertices of the
+    /// previous round as strong parents.
+    pub fn add_rounds(&mut self, rounds: std::ops::RangeInclusive<Round>) -> &mut Self {
+        let all: Vec<usize> = (0..self.owners.len()).collect();
+        for round in rounds {
+            for owner in 0..self.owners.len() {
+                self.add(owner, round, &all);
+            }
+        }
+        self
+    }
+
+    /// Add the vertex of `owner` in `round` with the vertices of the `parents` owners in the
+    /// previous round as strong parents.
+    pub fn add(&mut self, owner: usize, round: Round, parents: &[usize]) -> VertexHash {
+        let previous = &self.rounds[&(round - 1)];
+        let parents = parents
+            .iter()
+            .filter_map(|parent| previous.get(parent))
+            .map(|vertex| (vertex.hash(), vertex.round()))
+            .collect();
+        // the payload tells the vertices of an owner apart when their parents are the same
+        let block = Block::new(vec![format!("{} {}", owner, round).into_bytes()]);
+        let vertex = Vertex::new(self.owners[owner], round, block, parents);
+        let hash = vertex.hash();
+        self.rounds.entry(round).or_default().insert(owner, vertex);
+        hash
+    }
+
+    /// Add a weak edge from the vertex of `owner` in `round` to the vertex of `to_owner` in
+    /// `to_round`. It changes the hash of the vertex, so it has to come before the vertex is
+    /// used as a parent.
+    pub fn add_weak_edge(&mut self, owner: usize, round: Round, to_owner: usize, to_round: Round) -> &mut Self {
+        let parent = self.get(to_owner, to_round).hash();
+        self.rounds.get_mut(&round).and_then(|vertices| vertices.get_mut(&owner)).unwrap().add_parent(parent, to_round);
+        self
+    }
+
+    /// Owner of the leader vertex of `round`, which has to be the first round of a wave.
+    pub fn leader(&self, round: Round) -> usize {
+        self.committee.leader_schedule().leader_index(LeaderSeed::from_round(round).unwrap()).unwrap()
+    }
+
+    pub fn get(&self, owner: usize, round: Round) -> &Vertex {
+        &self.rounds[&round][&owner]
+    }
+
+    pub fn genesis(&self) -> Vec<Vertex> {
+        self.rounds[&GENESIS_ROUND].values().cloned().collect()
+    }
+
+    /// The vertices built above the genesis round, in round order.
+    pub fn vertices(&self) -> Vec<Vertex> {
+        self.rounds
+            .range(GENESIS_ROUND + 1..)
+            .flat_map(|(_, vertices)| vertices.values().cloned())
+            .collect()
+    }
+}
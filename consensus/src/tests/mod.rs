@@ -0,0 +1,4 @@
+mod dag;
+mod dag_builder;
+mod ordering;
+mod state;
@@ -0,0 +1,278 @@
+use std::path::Path;
+
+use model::Round;
+use model::committee::Committee;
+use model::parameters::{CommitRule, Parameters};
+use model::vertex::{Vertex, VertexHash};
+use tokio::sync::mpsc::channel;
+
+use crate::{order_offline, Consensus, ConsensusOutputs};
+use crate::commit_history::{Checkpoint, CommitEvent, CommitHistory};
+use crate::event_log::{ConsensusInput, EventLog};
+use crate::missing_rounds::MissingRounds;
+use crate::tests::dag_builder::DagBuilder;
+
+fn parameters(gc_depth: u64, commit_rule: CommitRule) -> Parameters {
+    Parameters {
+        gc_depth,
+        commit_rule,
+        ..Parameters::default()
+    }
+}
+
+fn hashes(vertices: &[Vertex]) -> Vec<VertexHash> {
+    vertices.iter().map(|vertex| vertex.hash()).collect()
+}
+
+/// A DAG whose vertex of the owner 3 in the round 2 is only reached through a weak edge from the
+/// round 8, more than `gc_depth` rounds below the leader of the wave 2 when `gc_depth` is 2.
+fn dag_with_a_late_weak_edge() -> DagBuilder {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=2);
+    for owner in 0..4 {
+        dag.add(owner, 3, &[0, 1, 2]);
+    }
+    dag.add_rounds(4..=8);
+    dag.add_weak_edge(0, 8, 3, 2);
+    dag.add_rounds(9..=13);
+    dag
+}
+
+#[test]
+fn batching_leaders_does_not_change_the_order() {
+    let dag = dag_with_a_late_weak_edge();
+    // the leaders of the waves 1 to 3 are ordered one by one, or all at once at the end of the
+    // wave 3, with the DAG garbage collected in between in the first case only
+    let one_by_one = order_offline(1, dag.vertices(), dag.committee.clone(), parameters(2, CommitRule::Immediate)).unwrap();
+    let in_one_batch = order_offline(1, dag.vertices(), dag.committee.clone(), parameters(2, CommitRule::Deferred { waves: 3 })).unwrap();
+
+    assert!(!one_by_one.is_empty());
+    assert_eq!(hashes(&one_by_one), hashes(&in_one_batch));
+    // below the floor of the leader of the wave 3 whichever way the leaders were batched
+    assert!(!one_by_one.iter().any(|vertex| vertex.hash() == dag.get(3, 2).hash()));
+}
+
+#[test]
+fn leaders_per_iteration_do_not_change_the_order() {
+    let dag = dag_with_a_late_weak_edge();
+    let order = |max_leaders_per_iteration| {
+        let parameters = Parameters {
+            max_leaders_per_iteration,
+            ..parameters(2, CommitRule::Deferred { waves: 3 })
+        };
+        hashes(&order_offline(1, dag.vertices(), dag.committee.clone(), parameters).unwrap())
+    };
+
+    let all_at_once = order(None);
+    assert!(!all_at_once.is_empty());
+    assert_eq!(order(Some(1)), all_at_once);
+}
+
+#[test]
+fn same_dag_gives_the_same_checkpoint_chain() {
+    let dag = dag_with_a_late_weak_edge();
+    let chain = |vertices: Vec<Vertex>| {
+        let history = CommitHistory::new(10);
+        let outputs = ConsensusOutputs { commit_history: Some(history.clone()), ..ConsensusOutputs::default() };
+        let mut consensus = Consensus::offline(1, dag.committee.clone(), parameters(2, CommitRule::Immediate), outputs);
+        consensus.order_offline(vertices);
+        let chain: Vec<Checkpoint> = history.recent_commits(10).iter().map(|commit| commit.checkpoint).collect();
+        assert_eq!(chain.last(), Some(&consensus.checkpoint()));
+        chain
+    };
+
+    let chain_in_order = chain(dag.vertices());
+    // the vertices of a round come in another order to the second node
+    let chain_in_reverse = chain(dag.vertices().into_iter().rev().collect());
+    assert!(chain_in_order.len() > 1);
+    assert_eq!(chain_in_order, chain_in_reverse);
+}
+
+/// The commits of the node 1 over the vertices, with the vertices it delivered.
+fn commits(dag: &DagBuilder, parameters: Parameters) -> Vec<(CommitEvent, Vec<Vertex>)> {
+    let history = CommitHistory::new(100);
+    let outputs = ConsensusOutputs { commit_history: Some(history.clone()), ..ConsensusOutputs::default() };
+    let mut ordered = Consensus::offline(1, dag.committee.clone(), parameters, outputs).order_offline(dag.vertices()).into_iter();
+    history
+        .recent_commits(100)
+        .into_iter()
+        .map(|commit| {
+            let delivered = ordered.by_ref().take(commit.delivered).collect();
+            (commit, delivered)
+        })
+        .collect()
+}
+
+fn is_committed_leader(dag: &DagBuilder, leader: &Vertex) -> bool {
+    commits(dag, parameters(50, CommitRule::Immediate)).iter().any(|(commit, _)| commit.leader == leader.hash())
+}
+
+/// A DAG up to `last_round` in which only `supporters` vertices of the round 8, the leader's own
+/// among them, have a strong path to the leader of the wave 2 in the round 5. The vertices of the
+/// other owners in the rounds 6 to 8 only refer to each other.
+fn dag_with_wave_2_support(supporters: usize, last_round: Round) -> DagBuilder {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=5);
+    let leader = dag.leader(5);
+    let all: Vec<usize> = (0..4).collect();
+    let others: Vec<usize> = all.iter().copied().filter(|owner| *owner != leader).collect();
+    let (supporting, not_supporting) = others.split_at(supporters - 1);
+    let not_supporting = not_supporting.to_vec();
+    for round in 6..=8 {
+        dag.add(leader, round, &all);
+        for owner in supporting {
+            dag.add(*owner, round, &all);
+        }
+        for owner in &not_supporting {
+            let parents = if round == 6 { &others } else { &not_supporting };
+            dag.add(*owner, round, parents);
+        }
+    }
+    dag.add_rounds(9..=last_round);
+    dag
+}
+
+#[test]
+fn causal_history_is_delivered_in_round_and_hash_order() {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=12);
+    let commits = commits(&dag, parameters(50, CommitRule::Immediate));
+
+    assert_eq!(commits.len(), 3);
+    // the genesis leader of the wave 1 is delivered together with the genesis
+    assert_eq!(commits[0].0.delivered, 0);
+    for (commit, delivered) in &commits[1..] {
+        let order: Vec<(Round, VertexHash)> = delivered.iter().map(|vertex| (vertex.round(), vertex.hash())).collect();
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(order, sorted);
+        // nothing of the leader's round but the leader is in its causal history
+        assert_eq!(delivered.last().map(|vertex| vertex.hash()), Some(commit.leader));
+    }
+}
+
+#[test]
+fn leader_with_a_quorum_of_support_is_committed_in_its_wave() {
+    let dag = dag_with_wave_2_support(3, 9);
+    assert!(is_committed_leader(&dag, dag.get(dag.leader(5), 5)));
+}
+
+#[test]
+fn leader_below_a_quorum_of_support_is_not_committed_in_its_wave() {
+    let dag = dag_with_wave_2_support(2, 9);
+    assert!(!is_committed_leader(&dag, dag.get(dag.leader(5), 5)));
+}
+
+#[test]
+fn skipped_leader_is_committed_through_the_next_leader() {
+    // the leader of the wave 3 is strongly linked to the one of the wave 2 through the vertex of
+    // its owner in the round 8
+    let dag = dag_with_wave_2_support(1, 12);
+    let commits = commits(&dag, parameters(50, CommitRule::Immediate));
+    let leaders: Vec<VertexHash> = commits.iter().map(|(commit, _)| commit.leader).collect();
+
+    let wave_2_leader = dag.get(dag.leader(5), 5).hash();
+    let wave_3_leader = dag.get(dag.leader(9), 9).hash();
+    assert_eq!(leaders[1..], [wave_2_leader, wave_3_leader]);
+}
+
+#[test]
+fn sparse_leader_is_not_committed() {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=4);
+    let leader = dag.leader(5);
+    for owner in 0..4 {
+        // two strong parents, below the quorum of 3
+        let parents: Vec<usize> = if owner == leader { vec![0, 1] } else { (0..4).collect() };
+        dag.add(owner, 5, &parents);
+    }
+    dag.add_rounds(6..=12);
+    let commits = commits(&dag, parameters(50, CommitRule::Immediate));
+
+    let sparse_leader = dag.get(leader, 5).hash();
+    assert!(commits.iter().any(|(commit, _)| commit.leader == dag.get(dag.leader(9), 9).hash()));
+    assert!(!commits.iter().any(|(commit, _)| commit.leader == sparse_leader));
+    // it is still delivered with the causal history of the next leader
+    assert!(commits.iter().any(|(_, delivered)| delivered.iter().any(|vertex| vertex.hash() == sparse_leader)));
+}
+
+#[test]
+fn wave_with_incomplete_rounds_is_left_undecided() {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=8);
+    let (sender, mut receiver) = channel(10);
+    let outputs = ConsensusOutputs { missing_rounds_sender: Some(sender), ..ConsensusOutputs::default() };
+    let mut consensus = Consensus::offline(1, dag.committee.clone(), parameters(50, CommitRule::Immediate), outputs);
+    // only 2 vertices of the round 3, below the quorum, between the leaders of the waves 1 and 2
+    let (late, admitted): (Vec<Vertex>, Vec<Vertex>) =
+        dag.vertices().into_iter().partition(|vertex| vertex.round() == 3 && vertex.owner() < dag.owners[2]);
+    for vertex in admitted {
+        consensus.state.dag.insert_vertex(vertex);
+    }
+
+    consensus.commit_wave(2);
+    assert_eq!(consensus.decided_wave, 0);
+    assert!(consensus.leaders_to_order.is_empty());
+    assert_eq!(receiver.try_recv().unwrap(), MissingRounds { wave: 2, from: 1, to: 5 });
+
+    // decided once the rounds are filled in
+    for vertex in late {
+        consensus.state.dag.insert_vertex(vertex);
+    }
+    consensus.commit_wave(2);
+    assert_eq!(consensus.decided_wave, 2);
+    assert_eq!(consensus.leaders_to_order.len(), 2);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn wave_0_and_round_0_have_no_leader() {
+    let dag = DagBuilder::new();
+    let mut consensus = Consensus::offline(1, dag.committee.clone(), Parameters::default(), ConsensusOutputs::default());
+
+    assert_eq!(consensus.expected_leader(0), None);
+    assert!(consensus.get_wave_vertex_leader(0).is_none());
+    consensus.check_leader_presence(0);
+    consensus.commit_wave(0);
+    assert_eq!(consensus.decided_wave, 0);
+    assert!(consensus.leaders_to_order.is_empty());
+    // the leader of the wave 1 is elected in the genesis round
+    assert!(consensus.expected_leader(1).is_some());
+    assert!(consensus.get_wave_vertex_leader(1).is_some());
+}
+
+/// The inputs recorded by the node 1 of a committee of 4 nodes running with the default
+/// parameters, and the commit log it wrote in the same run.
+const CAPTURED_EVENTS: &str = "src/tests/fixtures/four_nodes.events";
+const CAPTURED_COMMITS: &str = include_str!("fixtures/four_nodes.commits");
+
+#[test]
+fn captured_dag_is_ordered_as_in_the_run() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(CAPTURED_EVENTS);
+    let vertices = EventLog::read(&path)
+        .unwrap()
+        .into_iter()
+        .filter_map(|logged| match logged.input {
+            ConsensusInput::Vertex(vertex) => Some(vertex),
+            _ => None,
+        })
+        .collect();
+    let committed = order_offline(1, vertices, Committee::default(), Parameters::default()).unwrap();
+
+    // every line of the commit log starts with the round and the hash of the vertex
+    let expected: Vec<String> = CAPTURED_COMMITS
+        .lines()
+        .map(|line| line.split(' ').take(2).collect::<Vec<_>>().join(" "))
+        .collect();
+    let ordered: Vec<String> = committed
+        .iter()
+        .map(|vertex| format!("{} {}", vertex.round(), base64::encode(vertex.hash())))
+        .collect();
+    assert_eq!(ordered, expected);
+}
+
+#[test]
+fn unknown_node_cannot_order() {
+    let dag = dag_with_a_late_weak_edge();
+    assert!(order_offline(5, dag.vertices(), dag.committee.clone(), Parameters::default()).is_err());
+}
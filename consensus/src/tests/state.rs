@@ -0,0 +1,71 @@
+use model::block::Block;
+use model::vertex::Vertex;
+
+use crate::error::InvariantViolation;
+use crate::state::State;
+use crate::tests::dag_builder::DagBuilder;
+
+/// A DAG whose round 3 has only the vertices of the owners 0 and 1, below the quorum of 3.
+fn dag_with_an_incomplete_round() -> DagBuilder {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=2);
+    dag.add(0, 3, &[0, 1, 2, 3]);
+    dag.add(1, 3, &[0, 1, 2, 3]);
+    for owner in 0..4 {
+        dag.add(owner, 4, &[0, 1]);
+    }
+    dag
+}
+
+#[test]
+fn vertices_above_an_incomplete_round_wait_for_its_quorum() {
+    let mut dag = dag_with_an_incomplete_round();
+    let mut state = State::new(dag.genesis());
+    state.current_round = 4;
+    for vertex in dag.vertices() {
+        state.add_vertex(vertex);
+    }
+    assert_eq!(state.dag.vertices_count(&4), 0);
+    assert!(!state.dag.are_rounds_complete(2, 4));
+
+    dag.add(2, 3, &[0, 1, 2, 3]);
+    state.add_vertex(dag.get(2, 3).clone());
+    assert_eq!(state.dag.vertices_count(&4), 4);
+    assert!(state.dag.are_rounds_complete(2, 5));
+}
+
+#[test]
+fn gap_below_a_round_is_an_invariant_violation() {
+    let dag = dag_with_an_incomplete_round();
+    let mut state = State::new(dag.genesis());
+    state.current_round = 4;
+    // bypass the admission rule of the state
+    for vertex in dag.vertices() {
+        state.dag.insert_vertex(vertex);
+    }
+    let violations = state.check_invariants().unwrap_err();
+    assert!(violations.contains(&InvariantViolation::IncompleteRound { round: 3 }));
+}
+
+#[test]
+fn vertex_claiming_a_later_round_than_its_parents_is_dropped() {
+    let mut dag = DagBuilder::new();
+    dag.add_rounds(2..=2);
+    let mut state = State::new(dag.genesis());
+    state.current_round = 3;
+    for vertex in dag.vertices() {
+        state.add_vertex(vertex);
+    }
+    // built on the round 1, but claiming the round 3 where its parents' round is complete
+    let parents = dag.genesis().iter().map(|vertex| (vertex.hash(), vertex.round())).collect();
+    let spoofed = Vertex::new(dag.owners[0], 3, Block::new(vec![]), parents);
+
+    state.add_vertex(spoofed);
+    assert_eq!(state.dag.vertices_count(&3), 0);
+    assert_eq!(state.buffered(), 0);
+
+    // the owner's genuine vertex of the round is not affected
+    let genuine = dag.add(0, 3, &[0, 1, 2, 3]);
+    state.add_vertex(dag.get(0, 3).clone());
+    assert_eq!(state.dag.get_vertex_by_hash(&genuine).map(|vertex| vertex.round()), Some(3));
+}
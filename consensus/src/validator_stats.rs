@@ -0,0 +1,13 @@
+/// Liveness of a validator over the rounds which are still in the DAG.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ValidatorStats {
+    /// Finished rounds with a vertex of the validator.
+    pub present: u64,
+    /// Finished rounds without a vertex of the validator.
+    pub absent: u64,
+    /// Decided waves the validator was elected leader of, and its vertex was committed as leader.
+    pub leader_committed: u64,
+    /// Decided waves the validator was elected leader of, but no vertex of it was committed as
+    /// leader, because it was missing or did not get enough support.
+    pub leader_skipped: u64,
+}
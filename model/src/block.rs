@@ -1,25 +1,93 @@
+use ed25519_dalek::{PublicKey, Signature, Verifier as _};
 use serde::{Deserialize, Serialize};
 
+use crate::committee::NodePublicKey;
+use crate::encoding::encode_hash;
+use crate::key_store::KeyStore;
+use crate::merkle::{merkle_root, MerkleProof};
+
 pub type Transaction = Vec<u8>;
 pub type BlockHash = [u8; 32];
 
-#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+/// First byte of a prioritized transaction, followed by its priority. The prefix stays part of
+/// the transaction. Transactions starting with any other byte, like the `0` of the sample
+/// transactions, have no priority, which is the same as priority 0.
+pub const PRIORITY_TAG: u8 = 0xff;
+
+/// Priority of the transaction, higher goes first.
+pub fn transaction_priority(transaction: &[u8]) -> u8 {
+    match transaction {
+        [PRIORITY_TAG, priority, ..] => *priority,
+        _ => 0,
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Block {
     pub hash: BlockHash,
     pub transactions: Vec<Transaction>,
 }
 
+/// Empty block, with the hash of no transactions so that it passes `verify`.
+impl Default for Block {
+    fn default() -> Self {
+        Block::new(vec![])
+    }
+}
+
 impl Block {
     pub fn new(transactions: Vec<Transaction>) -> Self {
-        let encoded = bincode::serialize(&transactions).unwrap();
-        let hash = blake3::hash(&encoded).as_bytes().clone();
         Self {
-            hash,
+            hash: Self::compute_hash(&transactions),
             transactions
         }
     }
 
+    /// The hash of a block is the Merkle root of its transactions, so that a transaction can be
+    /// proven to be in the block without the other transactions.
+    fn compute_hash(transactions: &[Transaction]) -> BlockHash {
+        merkle_root(transactions)
+    }
+
+    /// Proof that the transaction at `index` is in the block, to be checked against the block hash.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        MerkleProof::new(&self.transactions, index)
+    }
+
+    /// Check that the hash is the one of the transactions, a block which does not match its hash
+    /// cannot be the block its producer referenced.
+    pub fn verify(&self) -> crate::Result<()> {
+        if Self::compute_hash(&self.transactions) == self.hash {
+            Ok(())
+        } else {
+            Err(crate::Error::CorruptedBlock(encode_hash(self.hash)))
+        }
+    }
+
     pub fn hash(&self) -> BlockHash {
         self.hash
     }
+
+    /// Sign the block hash, so that other nodes can check who produced the block.
+    pub fn sign(&self, key_store: &KeyStore) -> Signature {
+        key_store.sign(&self.hash)
+    }
+
+    pub fn verify_signature(&self, key: &PublicKey, signature: &Signature) -> crate::Result<()> {
+        key.verify(&self.hash, signature)
+            .map_err(|_| crate::Error::InvalidSignature(encode_hash(self.hash)))
+    }
+}
+
+/// Block as it is sent to the block receivers of the other nodes, by the block builder of its
+/// producer.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum BlockMessage {
+    Block(Block),
+    /// Block with the signature of its producer over the block hash.
+    SignedBlock {
+        author: NodePublicKey,
+        block: Block,
+        signature: Signature,
+    },
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// Set remembering at most `capacity` items, forgetting the oldest ones first. Used to recognise
+/// recently seen messages without growing forever.
+pub struct BoundedSet<T> {
+    capacity: usize,
+    items: HashSet<T>,
+    order: VecDeque<T>,
+}
+
+impl<T: Eq + Hash + Clone> BoundedSet<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            items: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Add the item and return true if it was not in the set yet.
+    pub fn insert(&mut self, item: T) -> bool {
+        if self.capacity == 0 || !self.items.insert(item.clone()) {
+            return false;
+        }
+        self.order.push_back(item);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
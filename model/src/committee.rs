@@ -1,17 +1,106 @@
 use std::collections::{HashMap};
 use std::net::SocketAddr;
-use ed25519_dalek::Keypair;
-use serde::{Deserialize};
+use std::path::Path;
+use ed25519_dalek::{Keypair, PublicKey};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Round, Wave};
+use crate::wave::WAVE;
 
 pub type Id = u32;
 pub type NodePublicKey = [u8; 32];
+pub type Stake = u64;
+
+/// Seed electing a wave leader, see `LeaderSchedule`. The seed of a wave is its number, so with
+/// equal stakes wave `w` is led by the validator at position `w % n` among the sorted keys of
+/// the `n` validators and the leaders rotate through the whole committee: with 4 validators and
+/// waves of 4 rounds starting in round 1, the waves starting in rounds 1, 5, 9 and 13 are led by
+/// the validators at positions 1, 2, 3 and 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LeaderSeed(u64);
+
+impl LeaderSeed {
+    pub fn from_wave(wave: Wave) -> Self {
+        Self(wave)
+    }
+
+    /// Seed of the wave whose leader is elected in `round`, `None` if `round` is not the first
+    /// round of a wave.
+    pub fn from_round(round: Round) -> Option<Self> {
+        if !WAVE.is_leader_round(round) {
+            return None;
+        }
+        WAVE.wave_of(round).map(Self::from_wave)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Leader election weighted by stake. The validators, sorted by key, hold as many tickets as
+/// their stake and a seed draws the ticket `seed % total stake`, so over any `total stake`
+/// consecutive waves every validator leads as many waves as its stake, on every node alike. The
+/// seed is the wave number, so the schedule is known in advance. It is built once, the
+/// committee does not change while the node runs.
+#[derive(Clone, Debug)]
+pub struct LeaderSchedule {
+    /// Validators sorted by key, each with the total stake up to it, its own included.
+    cumulative_stakes: Vec<(Stake, NodePublicKey)>,
+}
+
+impl LeaderSchedule {
+    pub fn new(stakes: impl IntoIterator<Item = (NodePublicKey, Stake)>) -> Self {
+        let mut stakes: Vec<(NodePublicKey, Stake)> = stakes.into_iter().collect();
+        stakes.sort();
+        let cumulative_stakes = stakes
+            .into_iter()
+            .scan(0, |total: &mut Stake, (key, stake)| {
+                *total += stake;
+                Some((*total, key))
+            })
+            .collect();
+        Self { cumulative_stakes }
+    }
+
+    pub fn total_stake(&self) -> Stake {
+        self.cumulative_stakes.last().map_or(0, |(total, _)| *total)
+    }
+
+    /// Position of the leader elected by `seed` among the sorted validator keys, `None` if there
+    /// is no stake to draw from.
+    pub fn leader_index(&self, seed: LeaderSeed) -> Option<usize> {
+        let ticket = seed.value().checked_rem(self.total_stake())?;
+        Some(self.cumulative_stakes.partition_point(|(total, _)| *total <= ticket))
+    }
+
+    /// Key of the leader elected by `seed`.
+    pub fn leader(&self, seed: LeaderSeed) -> Option<NodePublicKey> {
+        self.leader_index(seed).map(|index| self.cumulative_stakes[index].1)
+    }
+}
+
+/// Smallest committee tolerating a faulty validator (n = 3f + 1 with f = 1).
+pub const MIN_SAFE_COMMITTEE_SIZE: usize = 4;
 
-#[derive(Clone, Deserialize)]
+/// Public description of a validator, it holds no secret key.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Validator {
     pub address: SocketAddr,
     pub tx_address: SocketAddr,
     pub block_address: SocketAddr,
     pub public_key: NodePublicKey,
+    /// Key checking the signatures made by the validator.
+    pub verification_key: PublicKey,
+    /// Weight of the validator in the leader election, 1 if not set. Quorums count validators
+    /// whatever their stake.
+    #[serde(default = "default_stake")]
+    pub stake: Stake,
+}
+
+fn default_stake() -> Stake {
+    1
 }
 
 impl Validator {
@@ -23,45 +112,105 @@ impl Validator {
             tx_address: SocketAddr::new("0.0.0.0".parse().unwrap(), tx_port),
             block_address: SocketAddr::new("0.0.0.0".parse().unwrap(), block_port),
             public_key,
+            verification_key: keypair.public,
+            stake: default_stake(),
         }
     }
 
-    fn create_keypair(kps: String) -> Keypair {
+    pub fn with_stake(self, stake: Stake) -> Self {
+        Self { stake, ..self }
+    }
+
+    pub fn create_keypair(kps: String) -> Keypair {
         let bytes = hex::decode(kps).unwrap();
-        return Keypair::from_bytes(&bytes).unwrap();
+        Keypair::from_bytes(&bytes).unwrap()
     }
 
-    fn create_node_public_key_from(keypair: &Keypair) -> NodePublicKey {
+    pub(crate) fn create_node_public_key_from(keypair: &Keypair) -> NodePublicKey {
         let encoded = bincode::serialize(&keypair.public).unwrap();
-        blake3::hash(&encoded).as_bytes().clone()
+        *blake3::hash(&encoded).as_bytes()
     }
 }
 
-#[derive(Clone, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Committee {
     pub validators: HashMap<Id, Validator>,
 }
 
-impl Committee {
-    pub fn default() -> Self {
+/// Keypairs of the validators of the default committee, the validator with id `i` has the
+/// keypair at index `i - 1`.
+const DEFAULT_KEYPAIRS: [&str; 4] = [
+    "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416",
+    "5a353c630d3faf8e2d333a0983c1c71d5e9b6aed8f4959578fbeb3d3f3172886393b576de0ac1fe86a4dd416cf032543ac1bd066eb82585f779f6ce21237c0cd",
+    "6f4b736b9a6894858a81696d9c96cbdacf3d49099d212213f5abce33da18716f067f8a2b9aeb602cd4163291ebbf39e0e024634f3be19bde4c490465d9095a6b",
+    "3ae38eec96146c241f6cadf01995af14f027b23b8fecbc77dbc2e3ed5fec6fc3fb4fe5534f7affc9a8f1d99e290fdb91cc26777edd6fae480cad9f735d1b3680",
+];
+
+impl Default for Committee {
+    fn default() -> Self {
         let mut validators = HashMap::new();
-        validators.insert(1, Validator::new(
-            "ad7f2ee3958a7f3fa2c84931770f5773ef7694fdd0bb217d90f29a94199c9d7307ca3851515c89344639fe6a4077923068d1d7fc6106701213c61d34ef8e9416",
-            1234, 1244, 1254));
-        validators.insert(2, Validator::new(
-            "5a353c630d3faf8e2d333a0983c1c71d5e9b6aed8f4959578fbeb3d3f3172886393b576de0ac1fe86a4dd416cf032543ac1bd066eb82585f779f6ce21237c0cd",
-            1235, 1245, 1255));
-        validators.insert(3, Validator::new(
-            "6f4b736b9a6894858a81696d9c96cbdacf3d49099d212213f5abce33da18716f067f8a2b9aeb602cd4163291ebbf39e0e024634f3be19bde4c490465d9095a6b",
-            1236, 1246, 1256));
-        validators.insert(4, Validator::new(
-            "3ae38eec96146c241f6cadf01995af14f027b23b8fecbc77dbc2e3ed5fec6fc3fb4fe5534f7affc9a8f1d99e290fdb91cc26777edd6fae480cad9f735d1b3680",
-            1237, 1247, 1257));
+        for (i, keypair) in (0..).zip(DEFAULT_KEYPAIRS) {
+            validators.insert(i as Id + 1, Validator::new(keypair, 1234 + i, 1244 + i, 1254 + i));
+        }
 
         Self {
             validators
         }
     }
+}
+
+impl Committee {
+    /// Committee of the given validators. Committees too small to tolerate a single faulty
+    /// validator are refused unless `allow_unsafe_small_committee` is set (for local testing),
+    /// and so are validators without stake.
+    pub fn new(validators: HashMap<Id, Validator>, allow_unsafe_small_committee: bool) -> crate::Result<Self> {
+        let committee = Self { validators };
+        committee.check(allow_unsafe_small_committee)?;
+        Ok(committee)
+    }
+
+    /// Load a committee from a JSON file, as written by `to_file`. It is checked as with `new`.
+    pub fn from_file(path: &Path, allow_unsafe_small_committee: bool) -> crate::Result<Self> {
+        let invalid = |e: String| Error::InvalidCommittee(path.display().to_string(), e);
+        let content = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let committee: Self = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        committee.check(allow_unsafe_small_committee).map_err(|e| invalid(e.to_string()))?;
+        Ok(committee)
+    }
+
+    /// Write the committee to a JSON file, so that a committee built by a tool can be handed to
+    /// the nodes. Validators only hold public keys, no secret ends up in the file.
+    pub fn to_file(&self, path: &Path) -> crate::Result<()> {
+        let failed = |e: String| Error::CommitteeNotWritten(path.display().to_string(), e);
+        let content = serde_json::to_string_pretty(self).map_err(|e| failed(e.to_string()))?;
+        std::fs::write(path, content).map_err(|e| failed(e.to_string()))
+    }
+
+    /// Fail if the committee can not tolerate a faulty validator, or only warn about it when
+    /// `allow_unsafe_small_committee` is set. A committee without validators, or with a
+    /// validator without stake, cannot elect every leader and always fails.
+    fn check(&self, allow_unsafe_small_committee: bool) -> crate::Result<()> {
+        if self.size() == 0 {
+            return Err(crate::Error::EmptyCommittee);
+        }
+        if let Some((id, _)) = self.validators.iter().find(|(_, validator)| validator.stake == 0) {
+            return Err(crate::Error::ZeroStake(*id));
+        }
+        if self.size() >= MIN_SAFE_COMMITTEE_SIZE {
+            return Ok(());
+        }
+        if !allow_unsafe_small_committee {
+            return Err(crate::Error::CommitteeTooSmall(self.size()));
+        }
+        warn!("The committee has {} validators and does not tolerate any faulty validator", self.size());
+        Ok(())
+    }
+
+    /// Keypair of the validator `id` of the default committee.
+    pub fn default_keypair(id: Id) -> Option<Keypair> {
+        let index = (id as usize).checked_sub(1)?;
+        DEFAULT_KEYPAIRS.get(index).map(|keypair| Validator::create_keypair(String::from(*keypair)))
+    }
 
     pub fn size(&self) -> usize {
         self.validators.len()
@@ -72,10 +221,7 @@ impl Committee {
     }
 
     pub fn get_node_address(&self, id: Id) -> Option<SocketAddr> {
-        match self.validators.get(&id) {
-            Some(v) => Some(v.address),
-            None => None
-        }
+        self.validators.get(&id).map(|v| v.address)
     }
 
     pub fn get_node_addresses(&self) -> Vec<SocketAddr> {
@@ -103,10 +249,134 @@ impl Committee {
     }
 
     pub fn get_nodes_keys(&self) -> Vec<NodePublicKey> {
-        self.validators.iter().map(|v| v.1.public_key.clone()).collect()
+        self.validators.iter().map(|v| v.1.public_key).collect()
     }
 
     pub fn get_node_key(&self, id: Id) -> Option<NodePublicKey> {
         self.validators.get(&id).map(|v| v.public_key)
     }
-}
\ No newline at end of file
+
+    pub fn get_stakes(&self) -> Vec<(NodePublicKey, Stake)> {
+        self.validators.values().map(|v| (v.public_key, v.stake)).collect()
+    }
+
+    pub fn get_verification_key(&self, node_key: &NodePublicKey) -> Option<PublicKey> {
+        self.validators.values().find(|v| v.public_key == *node_key).map(|v| v.verification_key)
+    }
+
+    /// Leader election of the committee, by stake.
+    pub fn leader_schedule(&self) -> LeaderSchedule {
+        LeaderSchedule::new(self.get_stakes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaders_rotate_through_the_committee() {
+        let schedule = Committee::default().leader_schedule();
+        let position = |round| LeaderSeed::from_round(round).and_then(|seed| schedule.leader_index(seed));
+
+        assert_eq!(position(1), Some(1));
+        assert_eq!(position(5), Some(2));
+        assert_eq!(position(9), Some(3));
+        assert_eq!(position(13), Some(0));
+        // no leader is elected in the other rounds of a wave
+        assert_eq!(position(2), None);
+        assert_eq!(position(4), None);
+        assert_eq!(position(6), None);
+    }
+
+    #[test]
+    fn leaders_are_elected_in_proportion_to_their_stake() {
+        let mut validators = committee_of(4);
+        for (id, validator) in validators.iter_mut() {
+            validator.stake = *id as Stake;
+        }
+        let committee = Committee::new(validators, false).unwrap();
+        let schedule = committee.leader_schedule();
+        assert_eq!(schedule.total_stake(), 10);
+
+        let mut elected: HashMap<NodePublicKey, Stake> = HashMap::new();
+        for wave in 1..=10_000 {
+            *elected.entry(schedule.leader(LeaderSeed::from_wave(wave)).unwrap()).or_default() += 1;
+        }
+        for (key, stake) in committee.get_stakes() {
+            assert_eq!(elected[&key], stake * 1_000);
+        }
+
+        // every node elects the same leaders, whatever the order of its committee
+        let reversed = LeaderSchedule::new(committee.get_stakes().into_iter().rev());
+        for wave in 1..=100 {
+            assert_eq!(reversed.leader(LeaderSeed::from_wave(wave)), schedule.leader(LeaderSeed::from_wave(wave)));
+        }
+    }
+
+    #[test]
+    fn no_leader_without_stake() {
+        let schedule = LeaderSchedule::new(Vec::new());
+        assert_eq!(schedule.leader(LeaderSeed::from_wave(1)), None);
+    }
+
+    fn committee_of(size: usize) -> HashMap<Id, Validator> {
+        (0..size)
+            .map(|i| (i as Id + 1, Validator::new(DEFAULT_KEYPAIRS[i], 1234 + i as u16, 1244 + i as u16, 1254 + i as u16)))
+            .collect()
+    }
+
+    #[test]
+    fn committee_of_4_is_safe() {
+        assert!(Committee::new(committee_of(4), false).is_ok());
+    }
+
+    #[test]
+    fn committee_of_3_needs_the_unsafe_flag() {
+        assert!(matches!(Committee::new(committee_of(3), false), Err(Error::CommitteeTooSmall(3))));
+        assert!(Committee::new(committee_of(3), true).is_ok());
+    }
+
+    #[test]
+    fn empty_committee_is_refused() {
+        assert!(matches!(Committee::new(HashMap::new(), true), Err(Error::EmptyCommittee)));
+    }
+
+    #[test]
+    fn validator_without_stake_is_refused() {
+        let mut validators = committee_of(4);
+        validators.entry(2).and_modify(|validator| validator.stake = 0);
+        assert!(matches!(Committee::new(validators, false), Err(Error::ZeroStake(2))));
+    }
+
+    #[test]
+    fn committee_file_without_stakes_gives_a_stake_of_1() {
+        let mut json = serde_json::to_value(Committee::default()).unwrap();
+        for validator in json["validators"].as_object_mut().unwrap().values_mut() {
+            validator.as_object_mut().unwrap().remove("stake");
+        }
+        let committee: Committee = serde_json::from_value(json).unwrap();
+        assert!(committee.get_stakes().iter().all(|(_, stake)| *stake == 1));
+    }
+
+    #[test]
+    fn small_committee_file_is_refused() {
+        let path = std::env::temp_dir().join(format!("small-committee-{}.json", std::process::id()));
+        Committee { validators: committee_of(3) }.to_file(&path).unwrap();
+        let refused = Committee::from_file(&path, false);
+        let allowed = Committee::from_file(&path, true);
+        std::fs::remove_file(&path).unwrap();
+        assert!(refused.is_err());
+        assert_eq!(allowed.unwrap().size(), 3);
+    }
+
+    #[test]
+    fn committee_file_round_trip() {
+        let committee = Committee::default();
+        let path = std::env::temp_dir().join(format!("committee-{}.json", std::process::id()));
+        committee.to_file(&path).unwrap();
+        let read = Committee::from_file(&path, false);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read.unwrap(), committee);
+    }
+}
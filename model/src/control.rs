@@ -0,0 +1,23 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Shared switch to stop a node from producing blocks and proposing vertices without stopping it.
+/// A paused node still receives vertices, advances rounds and commits like the others.
+#[derive(Clone, Default)]
+pub struct ProductionSwitch {
+    paused: Arc<AtomicBool>,
+}
+
+impl ProductionSwitch {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+}
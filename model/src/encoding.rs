@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// How hashes and keys are rendered in logs and errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashEncoding {
+    Base64,
+    Hex,
+}
+
+/// Encoding used by `encode_hash`, shared by the whole process.
+static HASH_ENCODING: AtomicU8 = AtomicU8::new(HashEncoding::Base64 as u8);
+
+/// Render hashes and keys with `encoding` from now on. It is meant to be set once at start, the
+/// same for the whole process, so that logs of different components can be matched.
+pub fn set_hash_encoding(encoding: HashEncoding) {
+    HASH_ENCODING.store(encoding as u8, Ordering::Relaxed);
+}
+
+pub fn hash_encoding() -> HashEncoding {
+    match HASH_ENCODING.load(Ordering::Relaxed) {
+        x if x == HashEncoding::Hex as u8 => HashEncoding::Hex,
+        _ => HashEncoding::Base64,
+    }
+}
+
+/// A hash or key as it is shown in logs and errors, with the encoding set for the process.
+pub fn encode_hash<T: AsRef<[u8]>>(bytes: T) -> String {
+    match hash_encoding() {
+        HashEncoding::Base64 => base64::encode(bytes),
+        HashEncoding::Hex => hex::encode(bytes),
+    }
+}
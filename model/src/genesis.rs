@@ -0,0 +1,93 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::committee::{Committee, NodePublicKey, Stake};
+
+pub type GenesisHash = [u8; 32];
+
+/// Everything the nodes have to agree on before the first round: the validators taking part
+/// in the genesis round with their stake, and an optional application state blob. The genesis vertices carry the
+/// hash of the config, so nodes with a different config do not share any genesis vertex and
+/// cannot build on each other's vertices.
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq)]
+pub struct GenesisConfig {
+    /// Stake of every validator, which weighs its election as leader.
+    pub validators: BTreeMap<NodePublicKey, Stake>,
+    pub initial_state: Option<Vec<u8>>,
+}
+
+impl GenesisConfig {
+    pub fn new(validators: BTreeMap<NodePublicKey, Stake>, initial_state: Option<Vec<u8>>) -> Self {
+        Self {
+            validators,
+            initial_state,
+        }
+    }
+
+    pub fn from_committee(committee: &Committee) -> Self {
+        Self::new(committee.get_stakes().into_iter().collect(), None)
+    }
+
+    pub fn with_initial_state(self, initial_state: Vec<u8>) -> Self {
+        Self {
+            initial_state: Some(initial_state),
+            ..self
+        }
+    }
+
+    pub fn validators(&self) -> Vec<NodePublicKey> {
+        self.validators.keys().cloned().collect()
+    }
+
+    /// Stake of the validator, `None` if it is not a genesis validator.
+    pub fn stake(&self, validator: &NodePublicKey) -> Option<Stake> {
+        self.validators.get(validator).copied()
+    }
+
+    pub fn hash(&self) -> GenesisHash {
+        // validators are kept in a BTreeMap, so the encoding does not depend on the insertion order
+        let encoded = bincode::serialize(self).unwrap();
+        *blake3::hash(&encoded).as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::vertex::Vertex;
+
+    use super::*;
+
+    fn hashes(config: &GenesisConfig) -> HashSet<[u8; 32]> {
+        Vertex::genesis(config).iter().map(|vertex| vertex.hash()).collect()
+    }
+
+    #[test]
+    fn same_config_gives_the_same_genesis() {
+        let config = GenesisConfig::from_committee(&Committee::default());
+        assert_eq!(hashes(&config), hashes(&config.clone()));
+        assert_eq!(hashes(&config).len(), Committee::default().size());
+    }
+
+    #[test]
+    fn different_configs_share_no_genesis_vertex() {
+        let config = GenesisConfig::from_committee(&Committee::default());
+        let with_state = config.clone().with_initial_state(vec![1]);
+        assert!(hashes(&config).is_disjoint(&hashes(&with_state)));
+    }
+
+    #[test]
+    fn configs_with_other_stakes_share_no_genesis_vertex() {
+        let committee = Committee::default();
+        let config = GenesisConfig::from_committee(&committee);
+        let mut validators = committee.validators.clone();
+        validators.entry(1).and_modify(|validator| validator.stake = 2);
+        let staked = GenesisConfig::from_committee(&Committee::new(validators, false).unwrap());
+
+        assert_eq!(staked.stake(&committee.get_node_key(1).unwrap()), Some(2));
+        assert_eq!(staked.stake(&committee.get_node_key(2).unwrap()), Some(1));
+        assert!(hashes(&config).is_disjoint(&hashes(&staked)));
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::Path;
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer as _};
+
+use crate::committee::{Committee, Id, NodePublicKey, Validator};
+use crate::Error;
+
+/// The keypair of this node, and the only place its secret key is kept. Components which sign
+/// what they produce get the key store instead of the keypair.
+pub struct KeyStore {
+    keypair: Keypair,
+    public_key: NodePublicKey,
+}
+
+impl KeyStore {
+    pub fn new(keypair: Keypair) -> Self {
+        Self {
+            public_key: Validator::create_node_public_key_from(&keypair),
+            keypair,
+        }
+    }
+
+    /// Key store of the validator `id` of the default committee.
+    pub fn default_for(id: Id) -> Option<Self> {
+        Committee::default_keypair(id).map(Self::new)
+    }
+
+    /// Load the keypair from a file holding it hex encoded, the way the keypairs of the default
+    /// committee are written.
+    pub fn from_file(path: &Path) -> crate::Result<Self> {
+        let invalid = |message: String| Error::InvalidKey(path.display().to_string(), message);
+        let content = fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let bytes = hex::decode(content.trim()).map_err(|e| invalid(e.to_string()))?;
+        let keypair = Keypair::from_bytes(&bytes).map_err(|e| invalid(e.to_string()))?;
+        Ok(Self::new(keypair))
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.keypair.sign(message)
+    }
+
+    /// Key of the node in the committee.
+    pub fn public_key(&self) -> NodePublicKey {
+        self.public_key
+    }
+
+    /// Key checking the signatures of this key store.
+    pub fn verification_key(&self) -> PublicKey {
+        self.keypair.public
+    }
+}
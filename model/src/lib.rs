@@ -1,10 +1,11 @@
 use thiserror::Error;
 
-pub const DEFAULT_CHANNEL_CAPACITY: usize = 1_000;
-
 pub type Round = u64;
 pub type Wave = u64;
 
+/// Round of the genesis vertices, the first vertices a node proposes are in the next round.
+pub const GENESIS_ROUND: Round = 1;
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, Error)]
@@ -12,10 +13,55 @@ pub enum Error {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] Box<bincode::ErrorKind>),
 
+    #[error("Vertex {0} does not match its hash")]
+    CorruptedVertex(String),
+
+    #[error("Vertex {0} is malformed: {1}")]
+    MalformedVertex(String, &'static str),
+
+    #[error("Block {0} does not match its transactions")]
+    CorruptedBlock(String),
+
+    #[error("Invalid signature of {0}")]
+    InvalidSignature(String),
+
+    #[error("A committee of {0} validators does not tolerate any faulty validator")]
+    CommitteeTooSmall(usize),
+
+    #[error("A committee needs at least one validator")]
+    EmptyCommittee,
+
+    #[error("Validator {0} has no stake")]
+    ZeroStake(crate::committee::Id),
+
+    #[error("Failed to load the parameters from {0}: {1}")]
+    InvalidParameters(String, String),
+
+    #[error("Invalid parameters: {0}")]
+    ParametersOutOfRange(String),
+
+    #[error("Failed to load the committee from {0}: {1}")]
+    InvalidCommittee(String, String),
+
+    #[error("Failed to write the committee to {0}: {1}")]
+    CommitteeNotWritten(String, String),
+
+    #[error("Failed to load the key from {0}: {1}")]
+    InvalidKey(String, String),
+
     #[error("UnexpectedError {0}")]
     UnexpectedError(String),
 }
 
 pub mod vertex;
 pub mod block;
+pub mod cache;
+pub mod clock;
 pub mod committee;
+pub mod control;
+pub mod encoding;
+pub mod genesis;
+pub mod key_store;
+pub mod merkle;
+pub mod parameters;
+pub mod wave;
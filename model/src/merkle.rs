@@ -0,0 +1,126 @@
+use serde::{Deserialize, Serialize};
+
+use crate::block::{BlockHash, Transaction};
+
+/// Domain separation of leaves and inner nodes, so that a leaf can not pass for an inner node.
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Merkle root of the transactions. A level with an odd number of nodes moves its last node up
+/// as is. The root of no transactions is all zeroes.
+pub fn merkle_root(transactions: &[Transaction]) -> BlockHash {
+    levels(transactions)
+        .last()
+        .and_then(|level| level.first().copied())
+        .unwrap_or_default()
+}
+
+/// Proof that a transaction is part of the transactions with a given Merkle root.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Hashes to combine with, from the leaf up, together with whether the hash is on the left.
+    pub siblings: Vec<(BlockHash, bool)>,
+}
+
+impl MerkleProof {
+    /// Proof for the transaction at `index`, if there is one.
+    pub fn new(transactions: &[Transaction], index: usize) -> Option<Self> {
+        if index >= transactions.len() {
+            return None;
+        }
+        let levels = levels(transactions);
+        let mut siblings = Vec::new();
+        let mut index = index;
+        for level in &levels[..levels.len() - 1] {
+            let sibling = index ^ 1;
+            if let Some(hash) = level.get(sibling) {
+                siblings.push((*hash, sibling < index));
+            }
+            index /= 2;
+        }
+        Some(Self { siblings })
+    }
+
+    pub fn verify(&self, root: &BlockHash, transaction: &Transaction) -> bool {
+        let hash = self.siblings.iter().fold(leaf_hash(transaction), |hash, (sibling, is_left)| {
+            if *is_left {
+                node_hash(sibling, &hash)
+            } else {
+                node_hash(&hash, sibling)
+            }
+        });
+        hash == *root
+    }
+}
+
+fn levels(transactions: &[Transaction]) -> Vec<Vec<BlockHash>> {
+    if transactions.is_empty() {
+        return vec![];
+    }
+    let mut levels = vec![transactions.iter().map(leaf_hash).collect::<Vec<_>>()];
+    while levels.last().unwrap().len() > 1 {
+        let next = levels.last().unwrap()
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn leaf_hash(transaction: &Transaction) -> BlockHash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(transaction);
+    *hasher.finalize().as_bytes()
+}
+
+fn node_hash(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block::Block;
+
+    fn block(size: u8) -> Block {
+        Block::new((0..size).map(|i| vec![i; 3]).collect())
+    }
+
+    #[test]
+    fn every_transaction_has_a_proof() {
+        // odd sizes move the last node of a level up as is
+        for size in [1, 2, 3, 5] {
+            let block = block(size);
+            for (index, transaction) in block.transactions.iter().enumerate() {
+                let proof = block.proof(index).unwrap();
+                assert!(proof.verify(&block.hash(), transaction), "transaction {} of {}", index, size);
+            }
+        }
+    }
+
+    #[test]
+    fn proof_does_not_verify_another_transaction_or_root() {
+        let block = block(5);
+        let proof = block.proof(2).unwrap();
+
+        assert!(!proof.verify(&block.hash(), &block.transactions[3]));
+        assert!(!proof.verify(&block.hash(), &vec![9; 3]));
+        assert!(!proof.verify(&self::block(3).hash(), &block.transactions[2]));
+    }
+
+    #[test]
+    fn no_proof_past_the_last_transaction() {
+        for size in [0, 1, 5] {
+            assert!(block(size).proof(size as usize).is_none());
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Round, Wave};
+use crate::encoding::HashEncoding;
+use crate::wave::WAVE;
+
+/// Tunable knobs of a node. Every field has a sensible default so that a node can run with
+/// `Parameters::default()`, and a parameters file only has to list the fields it changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Parameters {
+    /// Delay (in ms) after which a node without blocks proposes a vertex with an empty block,
+    /// as long as there is a wave leader waiting to be committed.
+    pub heartbeat_delay: u64,
+    /// Number of rounds kept in the DAG below the round of the last committed leader. The causal
+    /// history of the next leader is only delivered down to that depth, so it has to exceed the
+    /// reach of the weak edges: `weak_edge_lookback` plus a wave.
+    pub gc_depth: Round,
+    /// Number of rounds the node may get ahead of its last committed leader before it slows down
+    /// to one proposal per heartbeat, even with blocks waiting. This keeps the uncommitted part of
+    /// the DAG from growing fast while commits lag behind, without stopping the rounds the commits
+    /// need. No limit if not set.
+    pub max_commit_gap: Option<Round>,
+    /// Number of rounds below the parents' round which are scanned for weak edges when a vertex
+    /// is proposed. Older vertices left without a path stay unreferenced.
+    pub weak_edge_lookback: Round,
+    /// Time (in ms) a transaction may wait in the block builder before it is dropped instead of
+    /// being sealed into a block. No expiry if not set. The client already got its "Ack" and is
+    /// not told about the expiry, so it has to look for its transaction in the committed blocks
+    /// and send it again if it does not show up.
+    pub transaction_ttl: Option<u64>,
+    /// Transactions shorter than this (in bytes) are rejected when they are received.
+    pub min_transaction_size: usize,
+    /// Exact size (in bytes) every received transaction must have, transactions of another size
+    /// are rejected. Any size from `min_transaction_size` up is accepted if not set.
+    pub transaction_size: Option<usize>,
+    /// Time (in ms) without a quorum of vertices for the current round after which the node
+    /// reports a quorum stall, and the interval between repeated reports.
+    pub quorum_stall_delay: u64,
+    /// Number of random peers a node forwards every newly received vertex to, on top of the
+    /// owner's own broadcast. Gossip is disabled with 0.
+    pub gossip_fanout: usize,
+    /// Largest vertex (in bytes) sent as a single message, larger vertices are sent in chunks.
+    pub max_vertex_message_size: usize,
+    /// Time (in ms) a node waits for a quorum of nodes to acknowledge its vertex before it sends
+    /// the same vertex again to the nodes which did not.
+    pub vertex_rebroadcast_delay: u64,
+    /// Failed connection attempts after which a message to a peer is abandoned. Messages are
+    /// retried until they are delivered if not set.
+    pub message_max_retries: Option<u32>,
+    /// Time (in ms) after which a message a peer did not acknowledge is abandoned. Messages are
+    /// retried until they are delivered if not set.
+    pub message_max_time: Option<u64>,
+    /// Run with a committee of less than 4 validators, which does not tolerate any faulty
+    /// validator. Only meant for local testing.
+    pub allow_unsafe_small_committee: bool,
+    /// Whether blocks are signed by their producer. Nodes requiring signatures reject unsigned
+    /// blocks, so it has to be set on every node of the committee at once.
+    pub sign_blocks: bool,
+    /// Number of received blocks whose signature can be verified at the same time.
+    pub verification_workers: usize,
+    /// Number of connections every receiver of the node (vertices, blocks, transactions) handles
+    /// at the same time. Further connections are closed as soon as they are accepted.
+    pub max_connections: usize,
+    /// Number of the last commits kept for inspection.
+    pub commit_history_size: usize,
+    /// When the wave leaders are committed and their causal history ordered.
+    pub commit_rule: CommitRule,
+    /// Number of committed leaders whose causal history is ordered in one iteration of consensus.
+    /// When more leaders are committed at once, after a gap, the others are ordered in the next
+    /// iterations so that vertices keep being processed in between. No limit if not set.
+    pub max_leaders_per_iteration: Option<usize>,
+    /// Number of leaders which have to be committed on top of a leader before the vertices it
+    /// committed are output as finalized. With 0 they are finalized as soon as they are committed.
+    pub finality_depth: usize,
+    /// Capacity of the channels between the node components. When a channel is full its sender
+    /// waits, which slows down the component feeding it, so larger capacities absorb bursts at the
+    /// cost of memory and of hiding a slow consumer for longer.
+    pub channel_capacities: ChannelCapacities,
+    /// How hashes and keys are rendered in the logs.
+    pub hash_encoding: HashEncoding,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            heartbeat_delay: 500,
+            gc_depth: 50,
+            max_commit_gap: None,
+            weak_edge_lookback: 40,
+            transaction_ttl: None,
+            min_transaction_size: 1,
+            transaction_size: None,
+            quorum_stall_delay: 5_000,
+            gossip_fanout: 0,
+            max_vertex_message_size: 1 << 20,
+            vertex_rebroadcast_delay: 1_000,
+            message_max_retries: None,
+            message_max_time: None,
+            allow_unsafe_small_committee: false,
+            sign_blocks: false,
+            verification_workers: 4,
+            max_connections: 1_000,
+            commit_history_size: 100,
+            commit_rule: CommitRule::Immediate,
+            max_leaders_per_iteration: None,
+            finality_depth: 0,
+            channel_capacities: ChannelCapacities::default(),
+            hash_encoding: HashEncoding::Base64,
+        }
+    }
+}
+
+impl Parameters {
+    /// Load the parameters from a JSON file. Missing fields keep their default value, unknown
+    /// fields are rejected so that a misspelled knob does not go unnoticed.
+    pub fn from_file(path: &Path) -> crate::Result<Self> {
+        let invalid = |e: String| Error::InvalidParameters(path.display().to_string(), e);
+        let content = std::fs::read_to_string(path).map_err(|e| invalid(e.to_string()))?;
+        let parameters: Self = serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?;
+        parameters.validate().map_err(|e| invalid(e.to_string()))?;
+        Ok(parameters)
+    }
+
+    /// Reject the combinations of values a node cannot run safely with.
+    pub fn validate(&self) -> crate::Result<()> {
+        let out_of_range = |e: String| Err(Error::ParametersOutOfRange(e));
+        // timers firing without delay keep the node busy, channels without capacity cannot be
+        // created at all
+        let not_zero = [
+            ("heartbeat_delay", self.heartbeat_delay),
+            ("quorum_stall_delay", self.quorum_stall_delay),
+            ("vertex_rebroadcast_delay", self.vertex_rebroadcast_delay),
+            ("max_vertex_message_size", self.max_vertex_message_size as u64),
+            ("verification_workers", self.verification_workers as u64),
+            ("max_connections", self.max_connections as u64),
+            ("channel_capacities.transactions", self.channel_capacities.transactions as u64),
+            ("channel_capacities.blocks", self.channel_capacities.blocks as u64),
+            ("channel_capacities.vertices", self.channel_capacities.vertices as u64),
+            ("channel_capacities.output", self.channel_capacities.output as u64),
+        ];
+        if let Some((name, _)) = not_zero.iter().find(|(_, value)| *value == 0) {
+            return out_of_range(format!("{} has to be above 0", name));
+        }
+        // a budget of 0 abandons every message before it is sent
+        let optional_not_zero = [
+            ("message_max_retries", self.message_max_retries.map(u64::from)),
+            ("message_max_time", self.message_max_time),
+        ];
+        if let Some((name, _)) = optional_not_zero.iter().find(|(_, value)| *value == Some(0)) {
+            return out_of_range(format!("{} has to be above 0 when it is set", name));
+        }
+        // the causal history of a leader is delivered down to `gc_depth` rounds below the previous
+        // leader, while the weak edges of the vertices just below the leader go down to
+        // `weak_edge_lookback` rounds below their parents
+        if self.weak_edge_lookback.saturating_add(WAVE.length()) >= self.gc_depth {
+            return out_of_range(format!(
+                "gc_depth ({}) has to exceed weak_edge_lookback ({}) plus a wave ({} rounds)",
+                self.gc_depth, self.weak_edge_lookback, WAVE.length()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Committing only every few waves amortizes the ordering walk over the DAG at the cost of
+/// latency. The total order is the same either way, since the leaders of the skipped waves are
+/// committed through the strong paths from the leader of the committing wave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommitRule {
+    /// Try to commit at the end of every wave.
+    Immediate,
+    /// Try to commit only at the end of every `waves`-th wave.
+    Deferred { waves: Wave },
+}
+
+impl CommitRule {
+    /// Whether a commit is attempted at the end of the wave.
+    pub fn is_commit_wave(&self, wave: Wave) -> bool {
+        match self {
+            CommitRule::Immediate => true,
+            CommitRule::Deferred { waves } => wave.is_multiple_of((*waves).max(1)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelCapacities {
+    /// Transactions from clients to the block builder. When it is full, clients get a "Busy"
+    /// answer instead of having their transaction queued.
+    pub transactions: usize,
+    /// Blocks from other nodes to consensus. A full channel holds up the block receivers and in
+    /// turn the acknowledgements to the block builders of the other nodes.
+    pub blocks: usize,
+    /// Vertices from the network to consensus and from consensus to the broadcaster. This is the
+    /// hot path: a full channel delays acknowledgements and stalls round progress.
+    pub vertices: usize,
+    /// Committed vertices handed to the application. A slow application backs up consensus.
+    pub output: usize,
+}
+
+impl Default for ChannelCapacities {
+    fn default() -> Self {
+        Self {
+            transactions: 1_000,
+            blocks: 1_000,
+            vertices: 1_000,
+            output: 1_000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_parameters_are_valid() {
+        assert!(Parameters::default().validate().is_ok());
+    }
+
+    #[test]
+    fn zero_capacities_and_delays_are_rejected() {
+        let mut parameters = Parameters::default();
+        parameters.channel_capacities.vertices = 0;
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { heartbeat_delay: 0, ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { verification_workers: 0, ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { max_connections: 0, ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { message_max_time: Some(0), ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+    }
+
+    #[test]
+    fn weak_edges_have_to_stay_above_the_gc_depth() {
+        let parameters = Parameters { gc_depth: 50, weak_edge_lookback: 50, ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { gc_depth: 50, weak_edge_lookback: 46, ..Parameters::default() };
+        assert!(parameters.validate().is_err());
+
+        let parameters = Parameters { gc_depth: 50, weak_edge_lookback: 45, ..Parameters::default() };
+        assert!(parameters.validate().is_ok());
+    }
+}
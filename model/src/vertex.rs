@@ -3,10 +3,16 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 use crate::block::Block;
 use crate::committee::NodePublicKey;
-use crate::Round;
+use crate::encoding::encode_hash;
+use crate::genesis::GenesisConfig;
+use crate::{GENESIS_ROUND, Round};
 
 pub type VertexHash = [u8; 32];
 
+/// Characters kept by the short encodings of hashes and keys, enough to tell vertices apart in
+/// the logs of a committee.
+pub const SHORT_ENCODING_LENGTH: usize = 8;
+
 #[derive(Clone, Serialize, Deserialize, Default)]
 pub struct Vertex {
     /// Vertex unique identifier
@@ -31,29 +37,99 @@ impl Vertex {
             parents,
             hash: VertexHash::default(),
         };
-        let encoded = bincode::serialize(&vertex).unwrap();
-        let hash = blake3::hash(&encoded).as_bytes().clone();
         Self {
-            hash,
+            hash: vertex.compute_hash(),
             ..vertex
         }
     }
 
-    pub fn genesis(nodes: Vec<NodePublicKey>) -> Vec<Self> {
-        nodes.iter().map(|owner| Vertex::new(*owner, 1, Block::default(), BTreeMap::new())).collect()
+    /// Check that the vertex content still matches its hash, which is not the case for vertices
+    /// that have been corrupted or tampered with after creation, and that its parents are valid.
+    pub fn verify(&self) -> crate::Result<()> {
+        if self.compute_hash() != self.hash {
+            return Err(crate::Error::CorruptedVertex(encode_hash(self.hash)));
+        }
+        self.verify_parents()
+    }
+
+    /// Parents have to be in older rounds than the vertex, which also keeps a vertex from being its
+    /// own parent. The DAG walks rely on rounds going down along the edges.
+    fn verify_parents(&self) -> crate::Result<()> {
+        if self.parents.contains_key(&self.hash) {
+            return Err(crate::Error::MalformedVertex(encode_hash(self.hash), "it is its own parent"));
+        }
+        if self.parents.values().any(|round| *round >= self.round) {
+            return Err(crate::Error::MalformedVertex(encode_hash(self.hash), "a parent is not in an older round"));
+        }
+        Ok(())
+    }
+
+    /// The identity of a vertex: its owner, round, block and parents, in this order. Parents are
+    /// kept sorted by hash, so the same vertex hashes the same on every node. Weak parents are
+    /// part of it as they change the causal history, and so the ordering, of the vertex.
+    fn compute_hash(&self) -> VertexHash {
+        let content = (&self.owner, self.round, &self.block, &self.parents);
+        let encoded = bincode::serialize(&content).unwrap();
+        *blake3::hash(&encoded).as_bytes()
+    }
+
+    pub fn genesis(config: &GenesisConfig) -> Vec<Self> {
+        // the config hash makes the genesis vertices, and so every vertex above them, differ
+        // between nodes which do not agree on the genesis config
+        let mut transactions = vec![config.hash().to_vec()];
+        transactions.extend(config.initial_state.clone());
+        let block = Block::new(transactions);
+        config.validators()
+            .iter()
+            .map(|owner| Vertex::new(*owner, GENESIS_ROUND, block.clone(), BTreeMap::new()))
+            .collect()
+    }
+
+    /// Genesis vertices are in the genesis round and have no parents, any other vertex
+    /// claiming the genesis round is malformed.
+    pub fn is_genesis(&self) -> bool {
+        self.round == GENESIS_ROUND && self.parents.is_empty()
     }
 
     pub fn add_parent(&mut self, parent_vertex_hash: VertexHash, round: Round) {
         self.parents.insert(parent_vertex_hash, round);
+        // parents are part of the vertex content, keep the hash in line with them
+        self.hash = self.compute_hash();
     }
 
     pub fn get_strong_parents(&self) -> BTreeMap<VertexHash, Round> {
-        self.parents.iter()
-            .filter(|(_, r)| self.is_previous_round(r))
-            .map(|(h, r)| (h.clone(), r.clone()))
+        self.strong_parents()
+            .map(|(h, r)| (*h, *r))
             .collect::<BTreeMap<VertexHash, Round>>()
     }
 
+    /// Parents in the previous round, without copying them.
+    pub fn strong_parents(&self) -> impl Iterator<Item = (&VertexHash, &Round)> {
+        self.parents.iter().filter(move |(_, r)| self.is_previous_round(r))
+    }
+
+    pub fn strong_parent_hashes(&self) -> impl Iterator<Item = &VertexHash> {
+        self.strong_parents().map(|(h, _)| h)
+    }
+
+    pub fn strong_parent_count(&self) -> usize {
+        self.strong_parents().count()
+    }
+
+    /// Whether the vertex has an edge, strong or weak, to the given vertex.
+    pub fn has_parent(&self, vertex_hash: &VertexHash) -> bool {
+        self.parents.contains_key(vertex_hash)
+    }
+
+    /// The round of the vertex comes right after the newest round of its parents, as it does
+    /// for every vertex built on the previous round. Genesis vertices have no parents.
+    pub fn is_round_consistent(&self) -> bool {
+        match self.parents.values().max() {
+            Some(newest_parent_round) => newest_parent_round.checked_add(1) == Some(self.round),
+            None => self.is_genesis(),
+        }
+    }
+
     pub fn get_all_parents(&self) -> BTreeMap<VertexHash, Round> {
         self.parents.clone()
     }
@@ -74,6 +150,10 @@ impl Vertex {
         &self.parents
     }
 
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
     pub fn owner(&self) -> NodePublicKey {
         self.owner
     }
@@ -82,19 +162,47 @@ impl Vertex {
         self.hash
     }
 
+    /// The hash in the encoding of the logs.
+    pub fn encoded_hash(&self) -> String {
+        encode_hash(self.hash)
+    }
+
+    pub fn encoded_owner(&self) -> String {
+        encode_hash(self.owner)
+    }
+
+    /// The first `SHORT_ENCODING_LENGTH` characters of the encoded hash.
+    pub fn short_encoded_hash(&self) -> String {
+        shorten(self.encoded_hash())
+    }
+
+    /// The first `SHORT_ENCODING_LENGTH` characters of the encoded owner.
+    pub fn short_encoded_owner(&self) -> String {
+        shorten(self.encoded_owner())
+    }
+
+    /// A parent claiming a round at or above the round of the vertex is never in the previous
+    /// round, it must not underflow the subtraction.
     fn is_previous_round(&self, previous_round: &Round) -> bool {
-        self.round - previous_round == 1
+        self.round.checked_sub(*previous_round) == Some(1)
     }
 }
 
+/// Base64 and hex encodings are ASCII, so any length is on a character boundary.
+fn shorten(mut encoded: String) -> String {
+    encoded.truncate(SHORT_ENCODING_LENGTH);
+    encoded
+}
+
+/// Short encodings keep the log lines readable, `Debug` has the full ones.
 impl fmt::Display for Vertex {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         write!(
             f,
             "Vertex ({}, {}) [owner: {}]",
             self.round(),
-            base64::encode(self.hash()),
-            base64::encode(self.owner())
+            self.short_encoded_hash(),
+            self.short_encoded_owner()
         )
     }
 }
@@ -105,8 +213,8 @@ impl fmt::Debug for Vertex {
             f,
             "Vertex ({}, {}) [owner: {}]",
             self.round(),
-            base64::encode(self.hash()),
-            base64::encode(self.owner())
+            self.encoded_hash(),
+            self.encoded_owner()
         )
     }
 }
@@ -115,4 +223,45 @@ impl PartialEq for Vertex {
     fn eq(&self, other: &Self) -> bool {
         self.hash == other.hash
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(round: Round, parents: &[([u8; 32], Round)]) -> Vertex {
+        Vertex::new([1; 32], round, Block::default(), parents.iter().copied().collect())
+    }
+
+    #[test]
+    fn parent_at_or_above_the_round_is_not_a_strong_parent() {
+        let vertex = vertex(3, &[([2; 32], 2), ([3; 32], 3), ([4; 32], 4), ([5; 32], Round::MAX)]);
+
+        assert_eq!(vertex.strong_parent_hashes().collect::<Vec<_>>(), vec![&[2; 32]]);
+        assert_eq!(vertex.strong_parent_count(), 1);
+        assert_eq!(vertex.get_strong_parents().len(), 1);
+        assert!(!vertex.is_round_consistent());
+        assert!(vertex.verify().is_err());
+    }
+
+    #[test]
+    fn vertex_listing_itself_as_parent_is_rejected() {
+        let mut vertex = vertex(3, &[([2; 32], 2)]);
+        // the hash covers the parents, so a vertex can only list the hash it had without itself
+        vertex.parents.insert(vertex.hash, 2);
+
+        assert!(vertex.verify().is_err());
+        assert!(matches!(vertex.verify_parents(), Err(crate::Error::MalformedVertex(_, _))));
+    }
+
+    #[test]
+    fn parent_in_the_same_or_a_higher_round_is_rejected() {
+        for parent_round in [3, 4] {
+            let vertex = vertex(3, &[([2; 32], 2), ([3; 32], parent_round)]);
+            assert!(
+                matches!(vertex.verify(), Err(crate::Error::MalformedVertex(_, _))),
+                "parent in the round {}", parent_round
+            );
+        }
+        assert!(vertex(3, &[([2; 32], 2), ([3; 32], 1)]).verify().is_ok());
+    }
+}
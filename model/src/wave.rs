@@ -0,0 +1,129 @@
+use crate::{GENESIS_ROUND, Round, Wave};
+
+/// DAG-Rider waves are made of 4 rounds.
+pub const WAVE: WaveSchedule = WaveSchedule::new(4);
+
+/// Maps rounds to waves of `length` rounds. Waves are numbered from 1 and the first wave starts
+/// at the genesis round, so with a length of 4 wave 1 is made of rounds 1 to 4, wave 2 of
+/// rounds 5 to 8 and so on. The wave leader is elected in the first round of the wave.
+#[derive(Clone, Copy, Debug)]
+pub struct WaveSchedule {
+    length: Round,
+}
+
+impl WaveSchedule {
+    pub const fn new(length: Round) -> Self {
+        Self { length }
+    }
+
+    pub fn length(&self) -> Round {
+        self.length
+    }
+
+    /// The wave of the round, `None` for rounds before the genesis round.
+    pub fn wave_of(&self, round: Round) -> Option<Wave> {
+        round.checked_sub(GENESIS_ROUND).map(|offset| offset / self.length + 1)
+    }
+
+    /// The `index`-th round (starting from 1) of the wave, `None` if there is no such wave or
+    /// no such round in a wave.
+    pub fn round_of_wave(&self, wave: Wave, index: Round) -> Option<Round> {
+        if wave == 0 || index == 0 || index > self.length {
+            return None;
+        }
+        self.length
+            .checked_mul(wave - 1)
+            .and_then(|offset| offset.checked_add(GENESIS_ROUND + index - 1))
+    }
+
+    pub fn first_round_of_wave(&self, wave: Wave) -> Option<Round> {
+        self.round_of_wave(wave, 1)
+    }
+
+    pub fn last_round_of_wave(&self, wave: Wave) -> Option<Round> {
+        self.round_of_wave(wave, self.length)
+    }
+
+    pub fn is_leader_round(&self, round: Round) -> bool {
+        round >= GENESIS_ROUND && (round - GENESIS_ROUND).is_multiple_of(self.length)
+    }
+
+    pub fn is_last_round_of_wave(&self, round: Round) -> bool {
+        round >= GENESIS_ROUND && (round - GENESIS_ROUND + 1).is_multiple_of(self.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_of_the_first_waves() {
+        // (round, wave, leader round, last round of its wave)
+        let rounds = [
+            (1, 1, true, false),
+            (4, 1, false, true),
+            (5, 2, true, false),
+            (8, 2, false, true),
+            (9, 3, true, false),
+            (12, 3, false, true),
+        ];
+        for (round, wave, leader, last) in rounds {
+            assert_eq!(WAVE.wave_of(round), Some(wave), "round {}", round);
+            assert_eq!(WAVE.is_leader_round(round), leader, "round {}", round);
+            assert_eq!(WAVE.is_last_round_of_wave(round), last, "round {}", round);
+        }
+        for (wave, first, last) in [(1, 1, 4), (2, 5, 8), (3, 9, 12)] {
+            assert_eq!(WAVE.first_round_of_wave(wave), Some(first));
+            assert_eq!(WAVE.last_round_of_wave(wave), Some(last));
+        }
+    }
+
+    #[test]
+    fn every_round_of_a_wave_maps_back_to_it() {
+        for length in [1, 3, 4, 5] {
+            let schedule = WaveSchedule::new(length);
+            for wave in 1..=3 {
+                let first = schedule.first_round_of_wave(wave).unwrap();
+                let last = schedule.last_round_of_wave(wave).unwrap();
+                assert_eq!(last - first + 1, length);
+                assert!(schedule.is_leader_round(first));
+                assert!(schedule.is_last_round_of_wave(last));
+                for round in first..=last {
+                    assert_eq!(schedule.wave_of(round), Some(wave), "length {} round {}", length, round);
+                    assert_eq!(schedule.is_leader_round(round), round == first);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn waves_of_3_rounds() {
+        let schedule = WaveSchedule::new(3);
+        assert_eq!(schedule.first_round_of_wave(2), Some(4));
+        assert_eq!(schedule.last_round_of_wave(2), Some(6));
+        assert_eq!(schedule.wave_of(3), Some(1));
+        assert_eq!(schedule.wave_of(4), Some(2));
+        assert_eq!(schedule.wave_of(7), Some(3));
+        assert!(schedule.is_leader_round(7));
+        assert!(!schedule.is_leader_round(5));
+        assert!(!schedule.is_last_round_of_wave(5));
+        assert!(schedule.is_last_round_of_wave(6));
+    }
+
+    #[test]
+    fn wave_0_and_round_0_do_not_underflow() {
+        assert_eq!(WAVE.wave_of(0), None);
+        assert!(!WAVE.is_leader_round(0));
+        assert!(!WAVE.is_last_round_of_wave(0));
+        assert_eq!(WAVE.first_round_of_wave(0), None);
+        assert_eq!(WAVE.last_round_of_wave(0), None);
+        assert_eq!(WAVE.round_of_wave(0, 1), None);
+        // no round 0 or 5 in a wave of 4 rounds
+        assert_eq!(WAVE.round_of_wave(1, 0), None);
+        assert_eq!(WAVE.round_of_wave(1, 5), None);
+        // nor a round past the last one
+        assert_eq!(WAVE.first_round_of_wave(Wave::MAX), None);
+        assert_eq!(WAVE.wave_of(GENESIS_ROUND), Some(1));
+    }
+}
@@ -10,15 +10,30 @@ pub enum NetworkError {
     #[error("Failed to accept connection: {0}")]
     FailedToListen(std::io::Error),
 
+    #[error("Too many connections, closed the connection with {0}")]
+    TooManyConnections(SocketAddr),
+
     #[error("Failed to send message to {0}: {1}")]
     FailedToSendMessage(SocketAddr, std::io::Error),
 
     #[error("Failed to receive message from {0}: {1}")]
     FailedToReceiveMessage(SocketAddr, std::io::Error),
 
+    #[error("Failed to send the ACK, the message is dropped: {0}")]
+    FailedToSendAck(std::io::Error),
+
     #[error("Failed to receive ACK from {0}")]
     FailedToReceiveAck(SocketAddr),
 
     #[error("Receive unexpected ACK from {0}")]
     UnexpectedAck(SocketAddr),
+
+    #[error("Messages to {0} ran out of time without an ACK, reconnect")]
+    AckTimeout(SocketAddr),
+
+    #[error("The queue to {0} is full, the message is not sent")]
+    QueueFull(SocketAddr),
+
+    #[error("The connection to {0} is closed, the message is not sent")]
+    ConnectionClosed(SocketAddr),
 }
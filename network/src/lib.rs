@@ -1,8 +1,10 @@
 pub mod error;
+pub mod lucky_picker;
 pub mod receiver;
 pub mod reliable_sender;
 pub mod simple_sender;
 
+pub use crate::lucky_picker::LuckyPicker;
 pub use crate::receiver::{MessageHandler, Receiver, Writer};
-pub use crate::reliable_sender::{CancelHandler, ReliableSender};
+pub use crate::reliable_sender::{CancelHandler, ReliableSender, RetryBudget};
 pub use crate::simple_sender::SimpleSender;
@@ -0,0 +1,84 @@
+use rand::prelude::SliceRandom as _;
+use rand::rngs::SmallRng;
+use rand::{Rng as _, SeedableRng as _};
+use std::net::SocketAddr;
+
+/// Picks the few nodes a `lucky_broadcast` goes to. The picks start at a random position of the
+/// sorted addresses and rotate across calls, so repeated calls with the same addresses reach all
+/// of them instead of hitting the same subset.
+pub struct LuckyPicker {
+    /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
+    rng: SmallRng,
+    /// Position in the sorted address list where the next pick starts.
+    offset: usize,
+}
+
+impl std::default::Default for LuckyPicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LuckyPicker {
+    pub fn new() -> Self {
+        Self::with_rng(SmallRng::from_entropy())
+    }
+
+    /// Create a picker whose picks are reproducible (useful for tests).
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_rng(SmallRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(mut rng: SmallRng) -> Self {
+        Self { offset: rng.gen(), rng }
+    }
+
+    /// Up to `nodes` of the addresses, in a random order.
+    pub fn pick(&mut self, mut addresses: Vec<SocketAddr>, nodes: usize) -> Vec<SocketAddr> {
+        if addresses.is_empty() {
+            return addresses;
+        }
+        addresses.sort();
+        let start = self.offset % addresses.len();
+        addresses.rotate_left(start);
+        addresses.truncate(nodes);
+        self.offset = self.offset.wrapping_add(nodes);
+        addresses.shuffle(&mut self.rng);
+        addresses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn addresses(count: u16) -> Vec<SocketAddr> {
+        (0..count).map(|i| SocketAddr::from(([127, 0, 0, 1], 9_000 + i))).collect()
+    }
+
+    #[test]
+    fn retries_reach_every_node() {
+        for seed in 0..20 {
+            let mut picker = LuckyPicker::with_seed(seed);
+            let mut reached = HashSet::new();
+            // 2 nodes per pick reach the 5 nodes within 3 picks
+            for _ in 0..3 {
+                let picked = picker.pick(addresses(5), 2);
+                assert_eq!(picked.len(), 2);
+                reached.extend(picked);
+            }
+            assert_eq!(reached.len(), 5, "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_picks() {
+        let mut first = LuckyPicker::with_seed(7);
+        let mut second = LuckyPicker::with_seed(7);
+        for _ in 0..10 {
+            assert_eq!(first.pick(addresses(7), 3), second.pick(addresses(7), 3));
+        }
+    }
+}
@@ -6,9 +6,14 @@ use futures::stream::StreamExt as _;
 use log::{debug, info, warn};
 use std::error::Error;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+/// Connections handled at the same time by default, see `Receiver::spawn_with_connection_limit`.
+pub const MAX_CONNECTIONS: usize = 1_000;
+
 /// Convenient alias for the writer end of the TCP channel.
 pub type Writer = SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>;
 
@@ -28,13 +33,29 @@ pub struct Receiver<Handler: MessageHandler> {
     address: SocketAddr,
     /// Struct responsible to define how to handle received messages.
     handler: Handler,
+    /// One permit per connection handled at the same time.
+    connection_slots: Arc<Semaphore>,
 }
 
 impl<Handler: MessageHandler> Receiver<Handler> {
-    /// Spawn a new network receiver handling connections from any incoming peer.
+    /// Spawn a new network receiver handling connections from any incoming peer, up to
+    /// `MAX_CONNECTIONS` at the same time.
     pub fn spawn(address: SocketAddr, handler: Handler) {
+        Self::spawn_with_connection_limit(address, handler, MAX_CONNECTIONS);
+    }
+
+    /// Spawn a new network receiver handling up to `max_connections` connections at the same
+    /// time. Connections beyond the limit are closed right away, so a flood of connections
+    /// cannot exhaust the file descriptors and tasks of the node.
+    pub fn spawn_with_connection_limit(address: SocketAddr, handler: Handler, max_connections: usize) {
         tokio::spawn(async move {
-            Self { address, handler }.run().await;
+            Self {
+                address,
+                handler,
+                connection_slots: Arc::new(Semaphore::new(max_connections)),
+            }
+            .run()
+            .await;
         });
     }
 
@@ -53,22 +74,31 @@ impl<Handler: MessageHandler> Receiver<Handler> {
                     continue;
                 }
             };
+            let permit = match self.connection_slots.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    // dropping the socket closes the connection
+                    warn!("{}", NetworkError::TooManyConnections(peer));
+                    continue;
+                }
+            };
             info!("Incoming connection established with {}", peer);
-            Self::spawn_runner(socket, peer, self.handler.clone()).await;
+            Self::spawn_runner(socket, peer, self.handler.clone(), permit).await;
         }
     }
 
     /// Spawn a new runner to handle a specific TCP connection. It receives messages and process them
-    /// using the provided handler.
-    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler) {
+    /// using the provided handler. The connection slot is freed once the connection is closed.
+    async fn spawn_runner(socket: TcpStream, peer: SocketAddr, handler: Handler, permit: OwnedSemaphorePermit) {
         tokio::spawn(async move {
+            let _permit = permit;
             let transport = Framed::new(socket, LengthDelimitedCodec::new());
             let (mut writer, mut reader) = transport.split();
             while let Some(frame) = reader.next().await {
                 match frame.map_err(|e| NetworkError::FailedToReceiveMessage(peer, e)) {
                     Ok(message) => {
                         if let Err(e) = handler.dispatch(&mut writer, message.freeze()).await {
-                            warn!("{}", e);
+                            warn!("Closed the connection with {}: {}", peer, e);
                             return;
                         }
                     }
@@ -82,3 +112,63 @@ impl<Handler: MessageHandler> Receiver<Handler> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::sink::SinkExt as _;
+    use tokio::time::{sleep, timeout, Duration};
+
+    use super::*;
+
+    #[derive(Clone)]
+    struct AckHandler;
+
+    #[async_trait]
+    impl MessageHandler for AckHandler {
+        async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+            writer.send(Bytes::from("Ack")).await?;
+            Ok(())
+        }
+    }
+
+    type Connection = Framed<TcpStream, LengthDelimitedCodec>;
+
+    async fn connect(address: SocketAddr) -> Connection {
+        loop {
+            // the receiver may not listen yet
+            if let Ok(stream) = TcpStream::connect(address).await {
+                return Framed::new(stream, LengthDelimitedCodec::new());
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Whether the message sent through the connection is acknowledged.
+    async fn is_served(connection: &mut Connection) -> bool {
+        if connection.send(Bytes::from("message")).await.is_err() {
+            return false;
+        }
+        let reply = timeout(Duration::from_secs(5), connection.next()).await.expect("No reply or close");
+        matches!(reply, Some(Ok(ack)) if ack == "Ack")
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_the_limit_are_closed() {
+        let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        Receiver::spawn_with_connection_limit(address, AckHandler, 2);
+
+        let mut first = connect(address).await;
+        assert!(is_served(&mut first).await);
+        let mut second = connect(address).await;
+        assert!(is_served(&mut second).await);
+        let mut third = connect(address).await;
+        assert!(!is_served(&mut third).await);
+
+        // the slot of a closed connection is free again
+        drop(first);
+        sleep(Duration::from_millis(100)).await;
+        let mut fourth = connect(address).await;
+        assert!(is_served(&mut fourth).await);
+        assert!(is_served(&mut second).await);
+    }
+}
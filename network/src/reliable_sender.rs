@@ -1,33 +1,77 @@
 use crate::error::NetworkError;
+use crate::lucky_picker::LuckyPicker;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
-use rand::prelude::SliceRandom as _;
-use rand::rngs::SmallRng;
-use rand::SeedableRng as _;
 use std::cmp::min;
 use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio::sync::oneshot;
-use tokio::time::{sleep, Duration};
+use tokio::time::{interval, sleep, Duration, Instant, Interval};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 /// Convenient alias for cancel handlers returned to the caller task.
 pub type CancelHandler = oneshot::Receiver<Bytes>;
 
+/// Messages queued for a single peer. Once the queue is full, new messages to the peer are refused.
+const PEER_QUEUE_CAPACITY: usize = 1_000;
+
+/// How often the messages are checked against the `max_time` of their retry budget.
+const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a message is retried before it is abandoned. A message runs out of its budget after
+/// `max_retries` failed attempts to reach the peer, or once it waited `max_time` without being
+/// acknowledged, which is checked every `EXPIRY_CHECK_INTERVAL` whether the peer is reachable or
+/// not. An abandoned message is reported to the caller as a failure of its cancel handler.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryBudget {
+    /// Failed connection attempts after which a message is abandoned, no limit if None.
+    pub max_retries: Option<u32>,
+    /// Time after which an undelivered message is abandoned, no limit if None.
+    pub max_time: Option<Duration>,
+}
+
+impl RetryBudget {
+    /// Retry until the message is delivered or cancelled.
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    fn is_exhausted(&self, retries: &Retries) -> bool {
+        self.max_retries.is_some_and(|max| retries.count >= max)
+            || self.max_time.is_some_and(|max| retries.since.elapsed() >= max)
+    }
+}
+
+/// Failed attempts to deliver a message, checked against the `RetryBudget`.
+#[derive(Clone, Copy, Debug)]
+struct Retries {
+    count: u32,
+    since: Instant,
+}
+
+impl Retries {
+    fn new() -> Self {
+        Self { count: 0, since: Instant::now() }
+    }
+}
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 /// This sender is 'reliable' in the sense that it keeps trying to re-transmit messages for which it didn't
-/// receive an ACK back (until they succeed or are canceled).
+/// receive an ACK back (until they succeed, are canceled or run out of their retry budget).
 pub struct ReliableSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<InnerMessage>>,
-    /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
-    rng: SmallRng,
+    /// Nodes the `lucky_broadcast` messages go to.
+    lucky: LuckyPicker,
+    /// Retry budget of the messages, unlimited by default.
+    retry_budget: RetryBudget,
 }
 
 impl std::default::Default for ReliableSender {
@@ -38,41 +82,71 @@ impl std::default::Default for ReliableSender {
 
 impl ReliableSender {
     pub fn new() -> Self {
+        Self::with_picker(LuckyPicker::new())
+    }
+
+    /// Create a sender whose `lucky_broadcast` picks are reproducible (useful for tests).
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_picker(LuckyPicker::with_seed(seed))
+    }
+
+    fn with_picker(lucky: LuckyPicker) -> Self {
         Self {
             connections: HashMap::new(),
-            rng: SmallRng::from_entropy(),
+            lucky,
+            retry_budget: RetryBudget::unlimited(),
         }
     }
 
+    /// Give up on messages which run out of `budget` instead of retrying them forever, so a dead
+    /// peer does not hold on to them.
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = budget;
+        self
+    }
+
     /// Helper function to spawn a new connection.
-    fn spawn_connection(address: SocketAddr) -> Sender<InnerMessage> {
-        let (tx, rx) = channel(1_000);
-        Connection::spawn(address, rx);
+    fn spawn_connection(address: SocketAddr, retry_budget: RetryBudget) -> Sender<InnerMessage> {
+        let (tx, rx) = channel(PEER_QUEUE_CAPACITY);
+        Connection::spawn(address, rx, retry_budget);
         tx
     }
 
-    /// Reliably send a message to a specific address.
-    pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> CancelHandler {
+    /// Reliably send a message to a specific address. Every peer has its own queue, so a slow
+    /// peer does not hold up the others: when its queue is full the message is refused with
+    /// `NetworkError::QueueFull`, and it is up to the caller to send it again later or give up.
+    pub async fn send(&mut self, address: SocketAddr, data: Bytes) -> Result<CancelHandler, NetworkError> {
         let (sender, receiver) = oneshot::channel();
-        self.connections
+        let message = InnerMessage {
+            data,
+            cancel_handler: sender,
+        };
+        let retry_budget = self.retry_budget;
+        let connection = self.connections
             .entry(address)
-            .or_insert_with(|| Self::spawn_connection(address))
-            .send(InnerMessage {
-                data,
-                cancel_handler: sender,
-            })
-            .await
-            .expect("Failed to send internal message");
-        receiver
-    }
-
-    /// Broadcast the message to all specified addresses in a reliable manner. It returns a vector of
-    /// cancel handlers ordered as the input `addresses` vector.
+            .or_insert_with(|| Self::spawn_connection(address, retry_budget));
+        let message = match connection.try_send(message) {
+            Ok(()) => return Ok(receiver),
+            Err(TrySendError::Full(_)) => return Err(NetworkError::QueueFull(address)),
+            Err(TrySendError::Closed(message)) => message,
+        };
+
+        // the connection task stopped, the message goes to a new one
+        warn!("The connection to {} was closed, open a new one", address);
+        let connection = Self::spawn_connection(address, retry_budget);
+        connection.try_send(message).map_err(|_| NetworkError::ConnectionClosed(address))?;
+        self.connections.insert(address, connection);
+        Ok(receiver)
+    }
+
+    /// Broadcast the message to all specified addresses in a reliable manner. It returns the
+    /// cancel handler of each address, or why the message was not sent to it, ordered as the
+    /// input `addresses` vector.
     pub async fn broadcast(
         &mut self,
         addresses: Vec<SocketAddr>,
         data: Bytes,
-    ) -> Vec<CancelHandler> {
+    ) -> Vec<Result<CancelHandler, NetworkError>> {
         let mut handlers = Vec::new();
         for address in addresses {
             let handler = self.send(address, data.clone()).await;
@@ -81,16 +155,16 @@ impl ReliableSender {
         handlers
     }
 
-    /// Pick a few addresses at random (specified by `nodes`) and send the message only to them.
-    /// It returns a vector of cancel handlers with no specific order.
+    /// Pick a few addresses (specified by `nodes`) and send the message only to them, see
+    /// `LuckyPicker` for how they are picked. It returns a vector of cancel handlers, or errors as
+    /// with `broadcast`, with no specific order.
     pub async fn lucky_broadcast(
         &mut self,
-        mut addresses: Vec<SocketAddr>,
+        addresses: Vec<SocketAddr>,
         data: Bytes,
         nodes: usize,
-    ) -> Vec<CancelHandler> {
-        addresses.shuffle(&mut self.rng);
-        addresses.truncate(nodes);
+    ) -> Vec<Result<CancelHandler, NetworkError>> {
+        let addresses = self.lucky.pick(addresses, nodes);
         self.broadcast(addresses, data).await
     }
 }
@@ -114,17 +188,20 @@ struct Connection {
     /// The initial delay to wait before re-attempting a connection (in ms).
     retry_delay: u64,
     /// Buffer keeping all messages that need to be re-transmitted.
-    buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>)>,
+    buffer: VecDeque<(Bytes, oneshot::Sender<Bytes>, Retries)>,
+    /// How long the messages are retried.
+    retry_budget: RetryBudget,
 }
 
 impl Connection {
-    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>) {
+    fn spawn(address: SocketAddr, receiver: Receiver<InnerMessage>, retry_budget: RetryBudget) {
         tokio::spawn(async move {
             Self {
                 address,
                 receiver,
                 retry_delay: 200,
                 buffer: VecDeque::new(),
+                retry_budget,
             }
             .run()
             .await;
@@ -135,6 +212,7 @@ impl Connection {
     async fn run(&mut self) {
         let mut delay = self.retry_delay;
         let mut retry = 0;
+        let mut expiry = interval(EXPIRY_CHECK_INTERVAL);
         loop {
             match TcpStream::connect(self.address).await {
                 Ok(stream) => {
@@ -146,11 +224,13 @@ impl Connection {
 
                     // Try to transmit all messages in the buffer and keep transmitting incoming messages.
                     // The following function only returns if there is an error.
-                    let error = self.keep_alive(stream).await;
+                    let error = self.keep_alive(stream, &mut expiry).await;
                     warn!("{}", error);
+                    self.spend_retry();
                 }
                 Err(e) => {
                     warn!("{}", NetworkError::FailedToConnect(self.address, retry, e));
+                    self.spend_retry();
                     let timer = sleep(Duration::from_millis(delay));
                     tokio::pin!(timer);
 
@@ -166,9 +246,11 @@ impl Connection {
                             // Drain the channel into the buffer to not saturate the channel and block the caller task.
                             // The caller is responsible to cleanup the buffer through the cancel handlers.
                             Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
-                                self.buffer.push_back((data, cancel_handler));
-                                self.buffer.retain(|(_, handler)| !handler.is_closed());
+                                self.buffer.push_back((data, cancel_handler, Retries::new()));
+                                self.buffer.retain(|(_, handler, _)| !handler.is_closed());
                             }
+
+                            _ = expiry.tick(), if self.retry_budget.max_time.is_some() => self.abandon_exhausted(),
                         }
                     }
                 }
@@ -176,8 +258,37 @@ impl Connection {
         }
     }
 
+    /// Count a failed attempt for every buffered message, and abandon the messages which ran out
+    /// of their retry budget.
+    fn spend_retry(&mut self) {
+        for (_, _, retries) in self.buffer.iter_mut() {
+            retries.count += 1;
+        }
+        self.abandon_exhausted();
+    }
+
+    /// Abandon the buffered messages which ran out of their retry budget. Dropping their handler
+    /// notifies the caller of the failure.
+    fn abandon_exhausted(&mut self) {
+        let budget = self.retry_budget;
+        let mut abandoned = 0;
+        self.buffer.retain(|(_, handler, retries)| {
+            if handler.is_closed() {
+                false
+            } else if budget.is_exhausted(retries) {
+                abandoned += 1;
+                false
+            } else {
+                true
+            }
+        });
+        if abandoned > 0 {
+            warn!("Abandoned {} messages to {} which ran out of their retry budget", abandoned, self.address);
+        }
+    }
+
     /// Transmit messages once we have established a connection.
-    async fn keep_alive(&mut self, stream: TcpStream) -> NetworkError {
+    async fn keep_alive(&mut self, stream: TcpStream, expiry: &mut Interval) -> NetworkError {
         // This buffer keeps all messages and handlers that we have successfully transmitted but for
         // which we are still waiting to receive an ACK.
         let mut pending_replies = VecDeque::new();
@@ -185,7 +296,7 @@ impl Connection {
         let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
         let error = 'connection: loop {
             // Try to send all messages of the buffer.
-            while let Some((data, handler)) = self.buffer.pop_front() {
+            while let Some((data, handler, retries)) = self.buffer.pop_front() {
                 // Skip messages that have been cancelled.
                 if handler.is_closed() {
                     continue;
@@ -196,11 +307,11 @@ impl Connection {
                     Ok(()) => {
                         // The message has been sent, we remove it from the buffer and add it to
                         // `pending_replies` while we wait for an ACK.
-                        pending_replies.push_back((data, handler));
+                        pending_replies.push_back((data, handler, retries));
                     }
                     Err(e) => {
                         // We failed to send the message, we put it back into the buffer.
-                        self.buffer.push_front((data, handler));
+                        self.buffer.push_front((data, handler, retries));
                         break 'connection NetworkError::FailedToSendMessage(self.address, e);
                     }
                 }
@@ -210,10 +321,10 @@ impl Connection {
             tokio::select! {
                 Some(InnerMessage{data, cancel_handler}) = self.receiver.recv() => {
                     // Add the message to the buffer of messages to send.
-                    self.buffer.push_back((data, cancel_handler));
+                    self.buffer.push_back((data, cancel_handler, Retries::new()));
                 },
                 response = reader.next() => {
-                    let (data, handler) = match pending_replies.pop_front() {
+                    let (data, handler, retries) = match pending_replies.pop_front() {
                         Some(message) => message,
                         None => break 'connection NetworkError::UnexpectedAck(self.address)
                     };
@@ -225,19 +336,110 @@ impl Connection {
                         _ => {
                             // Something has gone wrong (either the channel dropped or we failed to read from it).
                             // Put the message back in the buffer, we will try to send it again.
-                            pending_replies.push_front((data, handler));
+                            pending_replies.push_front((data, handler, retries));
                             break 'connection NetworkError::FailedToReceiveAck(self.address);
                         }
                     }
                 },
+                // ACKs come in order, so the oldest message waiting for one is the first to run out of
+                // time. The connection is dropped to abandon it, since it cannot be taken out of the
+                // line of messages waiting for an ACK.
+                _ = expiry.tick(), if self.retry_budget.max_time.is_some() => {
+                    if pending_replies.front().is_some_and(|(_, _, retries)| self.retry_budget.is_exhausted(retries)) {
+                        break 'connection NetworkError::AckTimeout(self.address);
+                    }
+                },
             }
         };
 
         // If we reach this code, it means something went wrong. Put the messages for which we didn't receive an ACK
         // back into the sending buffer, we will try to send them again once we manage to establish a new connection.
-        while let Some(message) = pending_replies.pop_back() {
-            self.buffer.push_front(message);
+        while let Some((data, handler, retries)) = pending_replies.pop_back() {
+            self.buffer.push_front((data, handler, retries));
         }
         error
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::error::Error;
+
+    use async_trait::async_trait;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    use crate::receiver::{MessageHandler, Receiver as NetworkReceiver, Writer};
+
+    use super::*;
+
+    /// Acknowledges the messages and reports the address they were received on.
+    #[derive(Clone)]
+    struct ReportingHandler {
+        address: SocketAddr,
+        reports: Sender<SocketAddr>,
+    }
+
+    #[async_trait]
+    impl MessageHandler for ReportingHandler {
+        async fn dispatch(&self, writer: &mut Writer, _message: Bytes) -> Result<(), Box<dyn Error>> {
+            // reported before the ACK, so the sender sees it once the message is acknowledged
+            self.reports.send(self.address).await?;
+            writer.send(Bytes::from("Ack")).await?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn lucky_broadcasts_reach_every_node() {
+        let (reports, mut reached) = channel(100);
+        let mut addresses = Vec::new();
+        for _ in 0..4 {
+            let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+            NetworkReceiver::spawn(address, ReportingHandler { address, reports: reports.clone() });
+            addresses.push(address);
+        }
+        let mut sender = ReliableSender::with_seed(1);
+
+        // one node per message, the picks rotate through the 4 nodes
+        for _ in 0..4 {
+            for handler in sender.lucky_broadcast(addresses.clone(), Bytes::from("message"), 1).await {
+                timeout(Duration::from_secs(5), handler.unwrap()).await.unwrap().unwrap();
+            }
+        }
+        let mut nodes = HashSet::new();
+        while let Ok(address) = reached.try_recv() {
+            nodes.insert(address);
+        }
+        assert_eq!(nodes, addresses.into_iter().collect());
+    }
+
+    #[tokio::test]
+    async fn message_to_a_dead_peer_runs_out_of_retries() {
+        // nothing listens on the port once the listener is gone
+        let address = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let mut sender = ReliableSender::new().with_retry_budget(RetryBudget { max_retries: Some(2), max_time: None });
+
+        let handler = sender.send(address, Bytes::from("message")).await.unwrap();
+        assert!(timeout(Duration::from_secs(5), handler).await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn message_without_ack_runs_out_of_time() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // the peer accepts the connections and never sends an ACK
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let budget = RetryBudget { max_retries: None, max_time: Some(Duration::from_millis(100)) };
+        let mut sender = ReliableSender::new().with_retry_budget(budget);
+
+        let handler = sender.send(address, Bytes::from("message")).await.unwrap();
+        assert!(timeout(Duration::from_secs(5), handler).await.unwrap().is_err());
+    }
+}
@@ -1,24 +1,28 @@
 use crate::error::NetworkError;
+use crate::lucky_picker::LuckyPicker;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
 use futures::stream::StreamExt as _;
 use log::{info, warn};
-use rand::prelude::SliceRandom as _;
-use rand::rngs::SmallRng;
-use rand::SeedableRng as _;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{channel, Receiver, Sender};
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+/// Messages queued for a single peer. Once the queue is full, new messages to the peer are dropped.
+const PEER_QUEUE_CAPACITY: usize = 1_000;
+
 /// We keep alive one TCP connection per peer, each connection is handled by a separate task (called `Connection`).
 /// We communicate with our 'connections' through a dedicated channel kept by the HashMap called `connections`.
 pub struct SimpleSender {
     /// A map holding the channels to our connections.
     connections: HashMap<SocketAddr, Sender<Bytes>>,
-    /// Small RNG just used to shuffle nodes and randomize connections (not crypto related).
-    rng: SmallRng,
+    /// Nodes the `lucky_broadcast` messages go to.
+    lucky: LuckyPicker,
+    /// Number of messages dropped because the queue to their peer was full.
+    dropped: u64,
 }
 
 impl std::default::Default for SimpleSender {
@@ -29,15 +33,25 @@ impl std::default::Default for SimpleSender {
 
 impl SimpleSender {
     pub fn new() -> Self {
+        Self::with_picker(LuckyPicker::new())
+    }
+
+    /// Create a sender whose `lucky_broadcast` picks are reproducible (useful for tests).
+    pub fn with_seed(seed: u64) -> Self {
+        Self::with_picker(LuckyPicker::with_seed(seed))
+    }
+
+    fn with_picker(lucky: LuckyPicker) -> Self {
         Self {
             connections: HashMap::new(),
-            rng: SmallRng::from_entropy(),
+            lucky,
+            dropped: 0,
         }
     }
 
     /// Helper function to spawn a new connection.
     fn spawn_connection(address: SocketAddr) -> Sender<Bytes> {
-        let (tx, rx) = channel(1_000);
+        let (tx, rx) = channel(PEER_QUEUE_CAPACITY);
         Connection::spawn(address, rx);
         tx
     }
@@ -47,18 +61,30 @@ impl SimpleSender {
     pub async fn send(&mut self, address: SocketAddr, data: Bytes) {
         // Try to re-use an existing connection if possible.
         if let Some(tx) = self.connections.get(&address) {
-            if tx.send(data.clone()).await.is_ok() {
-                return;
+            match tx.try_send(data.clone()) {
+                Ok(()) => return,
+                // a slow peer does not hold up the sends to the others
+                Err(TrySendError::Full(_)) => {
+                    self.dropped += 1;
+                    warn!("The queue to {} is full, dropped a message", address);
+                    return;
+                }
+                Err(TrySendError::Closed(_)) => (),
             }
         }
 
         // Otherwise make a new connection.
         let tx = Self::spawn_connection(address);
-        if tx.send(data).await.is_ok() {
+        if tx.try_send(data).is_ok() {
             self.connections.insert(address, tx);
         }
     }
 
+    /// Number of messages dropped so far because the queue to their peer was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
     /// Try (best-effort) to broadcast the message to all specified addresses.
     pub async fn broadcast(&mut self, addresses: Vec<SocketAddr>, data: Bytes) {
         for address in addresses {
@@ -66,18 +92,23 @@ impl SimpleSender {
         }
     }
 
-    /// Pick a few addresses at random (specified by `nodes`) and try (best-effort) to send the
-    /// message only to them. This is useful to pick nodes with whom to sync.
+    /// Pick a few addresses (specified by `nodes`) and try (best-effort) to send the message only
+    /// to them. This is useful to pick nodes with whom to sync. See `LuckyPicker` for how they are
+    /// picked: retries eventually reach every node.
     pub async fn lucky_broadcast(
         &mut self,
-        mut addresses: Vec<SocketAddr>,
+        addresses: Vec<SocketAddr>,
         data: Bytes,
         nodes: usize,
     ) {
-        addresses.shuffle(&mut self.rng);
-        addresses.truncate(nodes);
+        let addresses = self.lucky.pick(addresses, nodes);
         self.broadcast(addresses, data).await
     }
+
+    /// The addresses `lucky_broadcast` would send to, to send several messages to the same nodes.
+    pub fn lucky_pick(&mut self, addresses: Vec<SocketAddr>, nodes: usize) -> Vec<SocketAddr> {
+        self.lucky.pick(addresses, nodes)
+    }
 }
 
 /// A connection is responsible to establish and keep alive (if possible) a connection with a single peer.
@@ -136,3 +167,61 @@ impl Connection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, timeout, Duration};
+
+    use super::*;
+
+    const MESSAGES: usize = 3_000;
+
+    /// Number of messages received on the listener until the last one.
+    async fn count_messages(listener: TcpListener) -> usize {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = Framed::new(stream, LengthDelimitedCodec::new());
+        let mut count = 0;
+        while let Some(Ok(message)) = reader.next().await {
+            count += 1;
+            if message.as_ref() == b"last" {
+                break;
+            }
+        }
+        count
+    }
+
+    #[tokio::test]
+    async fn slow_peer_does_not_hold_up_the_others() {
+        // the slow peer accepts the connection and never reads from it
+        let slow = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut addresses = vec![slow.local_addr().unwrap()];
+        tokio::spawn(async move {
+            let _connection = slow.accept().await;
+            sleep(Duration::from_secs(60)).await;
+        });
+        let mut readers = Vec::new();
+        for _ in 0..2 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            addresses.push(listener.local_addr().unwrap());
+            readers.push(tokio::spawn(count_messages(listener)));
+        }
+
+        let mut sender = SimpleSender::new();
+        for i in 0..MESSAGES {
+            sender.broadcast(addresses.clone(), Bytes::from(vec![0u8; 16 * 1024])).await;
+            // give the connections a chance to send, like the rest of the node would
+            if i % 100 == 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        }
+        sender.broadcast(addresses.clone(), Bytes::from("last")).await;
+
+        for reader in readers {
+            let received = timeout(Duration::from_secs(10), reader).await.unwrap().unwrap();
+            assert_eq!(received, MESSAGES + 1);
+        }
+        // the socket buffers and the queue to the slow peer are full
+        assert!(sender.dropped() > 0);
+    }
+}
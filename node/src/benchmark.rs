@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use bytes::{BufMut as _, BytesMut};
+use futures::sink::SinkExt as _;
+use futures::stream::StreamExt as _;
+use log::{info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, sleep, Duration, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use model::committee::Id;
+use model::vertex::Vertex;
+
+/// Interval between two bursts of generated transactions.
+const BURST_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Send times of the sample transactions generated by this node, by transaction id.
+type SentTransactions = Arc<Mutex<HashMap<u64, Instant>>>;
+
+/// Generates sample transactions to the node's own transaction receiver at `rate` tx/s and
+/// measures how many transactions get committed and how long its own ones take to commit.
+/// Sample transactions are tagged like the ones of the `client`: a 0 byte followed by a u64 id,
+/// the id being made of the node id and a counter so that the nodes do not mix their samples.
+pub struct Benchmark {
+    node_id: Id,
+    target: SocketAddr,
+    rate: u64,
+    duration: Duration,
+}
+
+impl Benchmark {
+    pub fn new(node_id: Id, target: SocketAddr, rate: u64, duration: Duration) -> Self {
+        Self { node_id, target, rate, duration }
+    }
+
+    /// Run the benchmark over the committed vertices of the node and report the results.
+    pub async fn run(self, mut vertex_output_receiver: Receiver<Vertex>) -> Result<()> {
+        let sent: SentTransactions = Arc::default();
+        let transport = self.connect().await?;
+        tokio::spawn(Self::generate(transport, self.node_id, self.rate, sent.clone()));
+
+        let started = Instant::now();
+        let deadline = sleep(self.duration);
+        tokio::pin!(deadline);
+
+        let mut committed_transactions = 0u64;
+        let mut latencies = Vec::new();
+        loop {
+            tokio::select! {
+                Some(vertex) = vertex_output_receiver.recv() => {
+                    for transaction in &vertex.block().transactions {
+                        committed_transactions += 1;
+                        if let Some(sent_at) = Self::sample_id(transaction).and_then(|id| sent.lock().unwrap().remove(&id)) {
+                            latencies.push(sent_at.elapsed());
+                        }
+                    }
+                },
+                () = &mut deadline => break,
+            }
+        }
+
+        let elapsed = started.elapsed().as_secs_f64();
+        latencies.sort();
+        info!("Benchmark finished after {:.1} s", elapsed);
+        info!("Committed transactions: {} ({:.1} tx/s)", committed_transactions, committed_transactions as f64 / elapsed);
+        info!(
+            "Latency of {} own sample transactions: p50 {} ms, p90 {} ms, p99 {} ms",
+            latencies.len(),
+            Self::percentile(&latencies, 50),
+            Self::percentile(&latencies, 90),
+            Self::percentile(&latencies, 99)
+        );
+        Ok(())
+    }
+
+    async fn connect(&self) -> Result<Framed<TcpStream, LengthDelimitedCodec>> {
+        // the receiver of the node is spawned together with the benchmark, give it some time
+        for _ in 0..10 {
+            if let Ok(stream) = TcpStream::connect(self.target).await {
+                return Ok(Framed::new(stream, LengthDelimitedCodec::new()));
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        let stream = TcpStream::connect(self.target)
+            .await
+            .context(format!("Failed to connect to {}", self.target))?;
+        Ok(Framed::new(stream, LengthDelimitedCodec::new()))
+    }
+
+    async fn generate(transport: Framed<TcpStream, LengthDelimitedCodec>, node_id: Id, rate: u64, sent: SentTransactions) {
+        let (mut writer, mut reader) = transport.split();
+        // nothing is done with the replies, but they have to be read to not fill up the socket
+        tokio::spawn(async move { while reader.next().await.is_some() {} });
+
+        let bursts_per_second = (Duration::from_secs(1).as_millis() / BURST_INTERVAL.as_millis()) as u64;
+        let burst_size = (rate / bursts_per_second).max(1);
+        let mut timer = interval(BURST_INTERVAL);
+        let mut counter = 0u64;
+        loop {
+            timer.tick().await;
+            for _ in 0..burst_size {
+                let id = (node_id as u64) << 32 | counter;
+                counter += 1;
+
+                let mut tx = BytesMut::with_capacity(9);
+                tx.put_u8(0u8);
+                tx.put_u64(id);
+                sent.lock().unwrap().insert(id, Instant::now());
+                if let Err(e) = writer.send(tx.freeze()).await {
+                    warn!("Failed to send a sample transaction: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn sample_id(transaction: &[u8]) -> Option<u64> {
+        match transaction {
+            // the id may be followed by padding
+            [0, rest @ ..] if rest.len() >= 8 => Some(u64::from_be_bytes(rest[..8].try_into().unwrap())),
+            _ => None,
+        }
+    }
+
+    fn percentile(sorted: &[Duration], percent: usize) -> u128 {
+        if sorted.is_empty() {
+            return 0;
+        }
+        sorted[(sorted.len() - 1) * percent / 100].as_millis()
+    }
+}
@@ -15,6 +15,7 @@ async fn main() -> Result<()> {
     let matches = App::new(crate_name!())
         .version(crate_version!())
         .args_from_usage("<ADDR> 'The network address of the node where to send txs'")
+        .args_from_usage("--size=[INT] 'Size of the transactions in bytes, padded with zeroes (default 9, the smallest)'")
         .setting(AppSettings::ArgRequiredElseHelp)
         .get_matches();
 
@@ -28,10 +29,17 @@ async fn main() -> Result<()> {
         .parse::<SocketAddr>()
         .context("Invalid socket address format")?;
 
+    let size = matches
+        .value_of("size")
+        .unwrap_or("9")
+        .parse::<usize>()
+        .context("Invalid transaction size")?;
+
     info!("Node address: {}", target);
 
     let client = Client {
         target,
+        size,
     };
 
     // Start the benchmark.
@@ -40,18 +48,19 @@ async fn main() -> Result<()> {
 
 struct Client {
     target: SocketAddr,
+    /// Size of the transactions, never less than the tag and counter of the sample transactions.
+    size: usize,
 }
 
 impl Client {
     pub async fn send(&self) -> Result<()> {
         const TRANSACTION_COUNT: u64 = 40;
-        const TX_SIZE: usize = 64;
 
         let stream = TcpStream::connect(self.target)
             .await
             .context(format!("failed to connect to {}", self.target))?;
 
-        let mut tx = BytesMut::with_capacity(TX_SIZE);
+        let mut tx = BytesMut::with_capacity(self.size);
         let mut transport = Framed::new(stream, LengthDelimitedCodec::new());
 
         info!("Start sending transactions");
@@ -61,7 +70,7 @@ impl Client {
 
             tx.put_u8(0u8); // Sample txs start with 0.
             tx.put_u64(c); // This counter identifies the tx.
-            // tx.resize(TX_SIZE, 0u8);
+            tx.resize(self.size.max(tx.len()), 0u8);
             let bytes = tx.split().freeze();
 
             transport.send(bytes).await?;
@@ -0,0 +1,69 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use model::vertex::Vertex;
+
+/// When the commit log is flushed to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    /// Sync after every committed vertex.
+    Always,
+    /// Leave it to the OS.
+    Never,
+}
+
+/// Appends every committed vertex as a line `<round> <hash> <owner> <block hash>` to a file.
+/// Once the file grows over `max_size` bytes it is moved to `<path>.1` (replacing the previous
+/// one) and a new file is started.
+pub struct CommitLogWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    fsync: FsyncPolicy,
+}
+
+impl CommitLogWriter {
+    pub fn open(path: PathBuf, max_size: u64, fsync: FsyncPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            fsync,
+        })
+    }
+
+    pub fn append(&mut self, vertex: &Vertex) -> io::Result<()> {
+        let line = format!(
+            "{} {} {} {}\n",
+            vertex.round(),
+            base64::encode(vertex.hash()),
+            base64::encode(vertex.owner()),
+            base64::encode(vertex.block().hash())
+        );
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        if self.fsync == FsyncPolicy::Always {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        fs::rename(&self.path, rotated)?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -1,17 +1,40 @@
-use anyhow::{Result};
-use clap::{App, AppSettings, ArgMatches, SubCommand};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use clap::{App, ArgMatches, SubCommand};
 use env_logger::Env;
-use log::info;
+use log::{info, warn};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{channel, Receiver};
+use tokio::time::Duration;
 
-use consensus::Consensus;
+use consensus::{Consensus, ConsensusChannels, ConsensusOutputs};
+use consensus::commit_history::CommitHistory;
+use consensus::event_log::EventLog;
 use model::block::Block;
+use model::clock::SystemClock;
 use model::committee::{Committee, Id};
+use model::control::ProductionSwitch;
+use model::encoding::{encode_hash, set_hash_encoding};
+use model::genesis::GenesisConfig;
+use model::key_store::KeyStore;
+use model::parameters::Parameters;
 use model::vertex::Vertex;
-use transaction::TransactionCoordinator;
+use transaction::{MempoolWal, TransactionCoordinator};
 use vertex::vertex_coordinator::VertexCoordinator;
 
-pub const DEFAULT_CHANNEL_CAPACITY: usize = 1000;
+use crate::benchmark::Benchmark;
+use crate::commit_log::{CommitLogWriter, FsyncPolicy};
+
+mod benchmark;
+mod commit_log;
+
+/// Size after which the commit log is rotated.
+const COMMIT_LOG_MAX_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How often the last commit is reported, when there was a new one.
+const COMMIT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -22,6 +45,22 @@ async fn main() -> Result<()> {
             SubCommand::with_name("run")
                 .about("Run a node")
                 .args_from_usage("--id=<INT> 'Node id'")
+                .args_from_usage("--parameters=[FILE] 'JSON file with the node parameters, missing ones keep their default'")
+                .args_from_usage("--committee=[FILE] 'JSON file with the committee (default: the default committee)'")
+                .args_from_usage("--key-file=[FILE] 'File with the hex encoded keypair of the node (default: the keypair of the default committee)'")
+                .args_from_usage("--commit-log=[FILE] 'Append committed vertices to this file'")
+                .args_from_usage("--commit-log-fsync 'Sync the commit log to disk after every vertex'")
+                .args_from_usage("--mempool-wal=[FILE] 'Log the transactions waiting for a block to this file, and propose them again after a restart'")
+                .args_from_usage("--event-log=[FILE] 'Record the inputs of the consensus to this file, to replay them with the replay tool'")
+        )
+        .subcommand(
+            SubCommand::with_name("benchmark")
+                .about("Run a node feeding itself with sample transactions and report the commit throughput")
+                .args_from_usage("--id=<INT> 'Node id'")
+                .args_from_usage("--parameters=[FILE] 'JSON file with the node parameters, missing ones keep their default'")
+                .args_from_usage("--committee=[FILE] 'JSON file with the committee (default: the default committee)'")
+                .args_from_usage("--rate=[INT] 'Sample transactions sent per second (default 100)'")
+                .args_from_usage("--duration=[INT] 'Duration of the benchmark in seconds (default 30)'")
         )
         .get_matches();
 
@@ -30,6 +69,7 @@ async fn main() -> Result<()> {
 
     match matches.subcommand() {
         ("run", Some(sub_matches)) => run(sub_matches).await?,
+        ("benchmark", Some(sub_matches)) => benchmark(sub_matches).await?,
         _ => unreachable!(),
     }
     Ok(())
@@ -37,41 +77,181 @@ async fn main() -> Result<()> {
 
 async fn run(matches: &ArgMatches<'_>) -> Result<()> {
     let node_id = matches.value_of("id").unwrap().parse::<Id>().unwrap();
+    let commit_log = match matches.value_of("commit-log") {
+        Some(path) => {
+            let fsync = if matches.is_present("commit-log-fsync") { FsyncPolicy::Always } else { FsyncPolicy::Never };
+            Some(CommitLogWriter::open(PathBuf::from(path), COMMIT_LOG_MAX_SIZE, fsync)
+                .context(format!("Failed to open the commit log {}", path))?)
+        }
+        None => None
+    };
+    let mempool_wal = match matches.value_of("mempool-wal") {
+        Some(path) => Some(MempoolWal::open(PathBuf::from(path)).context(format!("Failed to open the mempool WAL {}", path))?),
+        None => None
+    };
+    let event_log = match matches.value_of("event-log") {
+        Some(path) => Some(EventLog::create(Path::new(path)).context(format!("Failed to create the event log {}", path))?),
+        None => None
+    };
+
+    let key_store = match matches.value_of("key-file") {
+        Some(path) => KeyStore::from_file(Path::new(path))?,
+        None => default_key_store(node_id)?,
+    };
+
+    let parameters = load_parameters(matches)?;
+    let committee = load_committee(matches, &parameters)?;
+    let vertex_output_receiver = start_node(node_id, committee, parameters, key_store, mempool_wal, event_log)?;
+    wait_and_print_vertexs(vertex_output_receiver, commit_log).await;
+    unreachable!();
+}
+
+async fn benchmark(matches: &ArgMatches<'_>) -> Result<()> {
+    let node_id = matches.value_of("id").unwrap().parse::<Id>().context("Invalid node id")?;
+    let rate = matches.value_of("rate").unwrap_or("100").parse::<u64>().context("Invalid rate")?;
+    let duration = matches.value_of("duration").unwrap_or("30").parse::<u64>().context("Invalid duration")?;
+    let parameters = load_parameters(matches)?;
+    let committee = load_committee(matches, &parameters)?;
+    let target = committee.get_tx_receiver_address(node_id).context("Unknown node id")?;
+
+    let vertex_output_receiver =
+        start_node(node_id, committee, parameters, default_key_store(node_id)?, None, None)?;
+    Benchmark::new(node_id, target, rate, Duration::from_secs(duration))
+        .run(vertex_output_receiver)
+        .await
+}
+
+fn load_parameters(matches: &ArgMatches<'_>) -> Result<Parameters> {
+    match matches.value_of("parameters") {
+        Some(path) => Ok(Parameters::from_file(Path::new(path))?),
+        None => Ok(Parameters::default()),
+    }
+}
 
-    let (vertex_output_sender, vertex_output_receiver) = channel::<Vertex>(DEFAULT_CHANNEL_CAPACITY);
+fn load_committee(matches: &ArgMatches<'_>, parameters: &Parameters) -> Result<Committee> {
+    match matches.value_of("committee") {
+        Some(path) => Ok(Committee::from_file(Path::new(path), parameters.allow_unsafe_small_committee)?),
+        None => Ok(Committee::default()),
+    }
+}
 
-    let (vertex_to_broadcast_sender, vertex_to_broadcast_receiver) = channel::<Vertex>(DEFAULT_CHANNEL_CAPACITY);
-    let (vertex_to_consensus_sender, vertex_to_consensus_receiver) = channel::<Vertex>(DEFAULT_CHANNEL_CAPACITY);
-    let (block_sender, block_receiver) = channel::<Block>(DEFAULT_CHANNEL_CAPACITY);
+fn default_key_store(node_id: Id) -> Result<KeyStore> {
+    KeyStore::default_for(node_id).context("No keypair for the node id")
+}
+
+/// Spawn all the components of the node and return the receiver of the committed vertices.
+fn start_node(
+    node_id: Id,
+    committee: Committee,
+    parameters: Parameters,
+    key_store: KeyStore,
+    mempool_wal: Option<MempoolWal>,
+    event_log: Option<EventLog>,
+) -> Result<Receiver<Vertex>> {
+    set_hash_encoding(parameters.hash_encoding);
+    if committee.get_node_key(node_id) != Some(key_store.public_key()) {
+        bail!("The key does not belong to the node {} of the committee", node_id);
+    }
+
+    // SIGUSR1 pauses the block production of the node and SIGUSR2 resumes it.
+    let switch = ProductionSwitch::default();
+    spawn_production_signals(switch.clone())?;
+
+    let capacities = &parameters.channel_capacities;
+    let (vertex_output_sender, vertex_output_receiver) = channel::<Vertex>(capacities.output);
+
+    let (vertex_to_broadcast_sender, vertex_to_broadcast_receiver) = channel::<Vertex>(capacities.vertices);
+    let (vertex_to_consensus_sender, vertex_to_consensus_receiver) = channel::<Vertex>(capacities.vertices);
+    let (block_sender, block_receiver) = channel::<Block>(capacities.blocks);
 
     VertexCoordinator::spawn(
         node_id,
-        Committee::default(),
+        committee.clone(),
         vertex_to_consensus_sender,
-        vertex_to_broadcast_receiver
+        vertex_to_broadcast_receiver,
+        parameters.clone(),
     );
 
+    let genesis = GenesisConfig::from_committee(&committee);
+    info!("Genesis config hash: {}", encode_hash(genesis.hash()));
+
     TransactionCoordinator::spawn(
         node_id,
-        Committee::default(),
-        block_sender
+        committee.clone(),
+        parameters.clone(),
+        block_sender,
+        switch.clone(),
+        key_store,
+        Arc::new(SystemClock),
+        mempool_wal,
     );
 
-    Consensus::spawn(
-        node_id,
-        Committee::default(),
-        vertex_to_consensus_receiver,
+    let commit_history = CommitHistory::new(parameters.commit_history_size);
+    spawn_commit_reports(commit_history.clone());
+    let channels = ConsensusChannels {
+        vertex_receiver: vertex_to_consensus_receiver,
+        blocks_receiver: block_receiver,
         vertex_to_broadcast_sender,
         vertex_output_sender,
-        block_receiver
-    );
+    };
+    let outputs = ConsensusOutputs {
+        commit_history: Some(commit_history),
+        event_log,
+        ..ConsensusOutputs::default()
+    };
+    Consensus::spawn(node_id, committee, parameters, genesis, channels, switch, outputs);
 
-    wait_and_print_vertexs(vertex_output_receiver).await;
-    unreachable!();
+    Ok(vertex_output_receiver)
+}
+
+fn spawn_production_signals(switch: ProductionSwitch) -> Result<()> {
+    let mut pause = signal(SignalKind::user_defined1()).context("Failed to listen for SIGUSR1")?;
+    let mut resume = signal(SignalKind::user_defined2()).context("Failed to listen for SIGUSR2")?;
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                Some(()) = pause.recv() => {
+                    info!("Block production paused");
+                    switch.pause();
+                },
+                Some(()) = resume.recv() => {
+                    info!("Block production resumed");
+                    switch.resume();
+                },
+                else => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Log the last commit with its checkpoint from time to time. Nodes which committed the same
+/// leader report the same checkpoint, so comparing the reports of the nodes for a wave tells
+/// whether their committed orders diverged.
+fn spawn_commit_reports(commit_history: CommitHistory) {
+    tokio::spawn(async move {
+        let mut reported = None;
+        loop {
+            tokio::time::sleep(COMMIT_REPORT_INTERVAL).await;
+            let last = commit_history.recent_commits(1).pop();
+            if let Some(commit) = last.filter(|commit| reported != Some(commit.leader)) {
+                info!(
+                    "Last commit: leader {} of the wave {} in the round {}, checkpoint {}",
+                    encode_hash(commit.leader), commit.wave, commit.round, encode_hash(commit.checkpoint)
+                );
+                reported = Some(commit.leader);
+            }
+        }
+    });
 }
 
-async fn wait_and_print_vertexs(mut vertex_output_receiver: Receiver<Vertex>) {
+async fn wait_and_print_vertexs(mut vertex_output_receiver: Receiver<Vertex>, mut commit_log: Option<CommitLogWriter>) {
     while let Some(vertex) = vertex_output_receiver.recv().await {
-        info!("Vertex committed: {}", vertex)
+        info!("Vertex committed: {}", vertex);
+        if let Some(log) = commit_log.as_mut() {
+            if let Err(e) = log.append(&vertex) {
+                warn!("Failed to append the vertex to the commit log: {}", e);
+            }
+        }
     }
 }
@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::{App, AppSettings, crate_name, crate_version};
+use env_logger::Env;
+use log::info;
+
+use consensus::{order_offline, Consensus};
+use consensus::event_log::{ConsensusInput, EventLog};
+use model::committee::{Committee, Id};
+use model::encoding::set_hash_encoding;
+use model::genesis::GenesisConfig;
+use model::parameters::Parameters;
+
+/// Replay the inputs recorded by a node run with `--event-log` and print the vertices the node
+/// committed, the same way the node logs them. With `--offline` only the recorded vertices are
+/// ordered, whatever the timing of the blocks and heartbeats was.
+#[tokio::main]
+async fn main() -> Result<()> {
+    let matches = App::new(crate_name!())
+        .version(crate_version!())
+        .args_from_usage("<FILE> 'Event log recorded by the node'")
+        .args_from_usage("--id=<INT> 'Id of the node which recorded the log'")
+        .args_from_usage("--parameters=[FILE] 'JSON file with the parameters the node ran with'")
+        .args_from_usage("--committee=[FILE] 'JSON file with the committee the node ran with'")
+        .args_from_usage("--offline 'Only order the vertices of the log, with consensus::order_offline'")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .get_matches();
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .format_timestamp_millis()
+        .init();
+
+    let path = matches.value_of("FILE").unwrap();
+    let node_id = matches.value_of("id").unwrap().parse::<Id>().context("Invalid node id")?;
+    let parameters = match matches.value_of("parameters") {
+        Some(path) => Parameters::from_file(Path::new(path))?,
+        None => Parameters::default(),
+    };
+    let committee = match matches.value_of("committee") {
+        Some(path) => Committee::from_file(Path::new(path), parameters.allow_unsafe_small_committee)?,
+        None => Committee::default(),
+    };
+
+    set_hash_encoding(parameters.hash_encoding);
+
+    let inputs = EventLog::read(Path::new(path)).context(format!("Failed to read the event log {}", path))?;
+    info!("Replaying {} inputs of the node {}", inputs.len(), node_id);
+
+    let committed = if matches.is_present("offline") {
+        let vertices = inputs
+            .into_iter()
+            .filter_map(|logged| match logged.input {
+                ConsensusInput::Vertex(vertex) => Some(vertex),
+                _ => None,
+            })
+            .collect();
+        order_offline(node_id, vertices, committee, parameters)?
+    } else {
+        let genesis = GenesisConfig::from_committee(&committee);
+        Consensus::replay(node_id, committee, parameters, genesis, inputs).await
+    };
+    for vertex in &committed {
+        info!("Vertex committed: {}", vertex);
+    }
+    info!("Replay committed {} vertices", committed.len());
+    Ok(())
+}
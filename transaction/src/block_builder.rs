@@ -1,58 +1,187 @@
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use bytes::Bytes;
-use log::{debug, error, info};
+use log::{error, info, warn};
 use tokio::sync::mpsc::{Receiver};
 
-use model::block::{Block, Transaction};
+use model::block::{transaction_priority, Block, BlockMessage, Transaction};
+use model::clock::Clock;
 use model::committee::Committee;
-use network::ReliableSender;
+use model::control::ProductionSwitch;
+use model::key_store::KeyStore;
+use model::parameters::Parameters;
+use network::{ReliableSender, RetryBudget};
 
-use crate::transaction_coordinator::BlockMessage;
+use crate::mempool_wal::MempoolWal;
 
 const BATCH_SIZE: usize = 10;
 
 pub struct BlockBuilder {
     committee: Committee,
+    transaction_ttl: Option<Duration>,
     transaction_receiver: Receiver<Transaction>,
-    current_transactions: Vec<Transaction>,
+    /// Transactions waiting for a block together with the time they were received.
+    current_transactions: Vec<(Instant, Transaction)>,
+    /// Number of transactions dropped because they expired, after they were acknowledged.
+    expired_transactions: u64,
     network: ReliableSender,
+    /// No blocks are sealed while paused, transactions keep waiting (and expiring).
+    switch: ProductionSwitch,
+    /// Keys of this node to sign the blocks with, blocks are not signed if not set.
+    signer: Option<KeyStore>,
+    /// Time source of the transaction expiry.
+    clock: Arc<dyn Clock>,
+    /// Where the waiting transactions are logged to survive a restart, if set. The transactions
+    /// are logged by the receiver before they are acknowledged, the block builder takes them out
+    /// once they are sealed or expired.
+    wal: Option<Arc<Mutex<MempoolWal>>>,
 }
 
 impl BlockBuilder {
     pub fn spawn(
         transaction_receiver: Receiver<Transaction>,
         committee: Committee,
+        parameters: Parameters,
+        switch: ProductionSwitch,
+        signer: Option<KeyStore>,
+        clock: Arc<dyn Clock>,
+        wal: Option<Arc<Mutex<MempoolWal>>>,
     ) {
         tokio::spawn(async move {
-            Self {
+            let mut block_builder = Self {
                 committee,
+                transaction_ttl: parameters.transaction_ttl.map(Duration::from_millis),
                 transaction_receiver,
                 current_transactions: vec![],
-                network: ReliableSender::new(),
-            }
-                .run()
-                .await;
+                expired_transactions: 0,
+                network: ReliableSender::new().with_retry_budget(RetryBudget {
+                    max_retries: parameters.message_max_retries,
+                    max_time: parameters.message_max_time.map(Duration::from_millis),
+                }),
+                switch,
+                signer,
+                clock,
+                wal,
+            };
+            block_builder.recover_transactions().await;
+            block_builder.run().await;
+        });
+    }
+
+    /// Take back the transactions of the WAL which were not sealed into a block before the node
+    /// stopped. Their expiry starts over.
+    async fn recover_transactions(&mut self) {
+        let wal = match &self.wal {
+            Some(wal) => wal.clone(),
+            None => return,
+        };
+        let recovered = tokio::task::spawn_blocking(move || {
+            let mut wal = wal.lock().unwrap();
+            let recovered = wal.recover()?;
+            // rewrite the log without the removed transactions and an entry cut short by a crash
+            wal.reset(recovered.iter())?;
+            Ok::<_, std::io::Error>(recovered)
         });
+        let recovered = match recovered.await.expect("Failed to recover the mempool WAL") {
+            Ok(recovered) => recovered,
+            Err(e) => {
+                error!("Failed to recover the transactions of the mempool WAL: {}", e);
+                return;
+            }
+        };
+        info!("BlockBuilder recovered {} transactions from the mempool WAL", recovered.len());
+        let now = self.clock.now();
+        self.current_transactions.extend(recovered.into_iter().map(|tx| (now, tx)));
     }
 
     async fn run(&mut self) {
+        // the recovered transactions do not wait for a batch to fill up, no client is sending
+        // them again
+        if !self.current_transactions.is_empty() && !self.switch.is_paused() {
+            info!("BlockBuilder seals the recovered transactions into a block");
+            self.seal_block().await;
+        }
+
         while let Some(transaction) = self.transaction_receiver.recv().await {
             info!("BlockBuilder received transaction {:?}", transaction);
-            self.current_transactions.push(transaction);
+            self.current_transactions.push((self.clock.now(), transaction));
+            self.drop_expired_transactions();
 
-            if self.current_transactions.len() >= BATCH_SIZE {
+            if self.current_transactions.len() >= BATCH_SIZE && !self.switch.is_paused() {
                 info!("BlockBuilder has enough transactions to make a block. Broadcast it to others");
-                let message = BlockMessage::Block(Block::new(self.current_transactions.drain(..).collect()));
-                let serialized = bincode::serialize(&message).expect("Failed to serialize the block");
+                self.seal_block().await;
+            }
+        }
+    }
 
-                // Broadcast the block through the network.
-                let bytes = Bytes::from(serialized.clone());
-                let handlers = self.network.broadcast(self.committee.get_block_receiver_addresses(), bytes).await;
-                for h in handlers {
+    /// Seal all the waiting transactions into a block and broadcast it.
+    async fn seal_block(&mut self) {
+        let mut transactions: Vec<Transaction> = self.current_transactions.drain(..).map(|(_, tx)| tx).collect();
+        // stable, so transactions of the same priority keep their arrival order
+        transactions.sort_by_key(|tx| Reverse(transaction_priority(tx)));
+        let block = Block::new(transactions);
+        let message = match &self.signer {
+            Some(key_store) => BlockMessage::SignedBlock {
+                author: key_store.public_key(),
+                signature: block.sign(key_store),
+                block,
+            },
+            None => BlockMessage::Block(block),
+        };
+        let serialized = bincode::serialize(&message).expect("Failed to serialize the block");
+
+        // Broadcast the block through the network.
+        let bytes = Bytes::from(serialized.clone());
+        let handlers = self.network.broadcast(self.committee.get_block_receiver_addresses(), bytes).await;
+        for h in handlers {
+            match h {
+                Ok(h) => {
                     if let Err(e) = h.await {
                         error!("Broadcast of the block was not successful: {:?}", e);
                     }
                 }
+                Err(e) => error!("Broadcast of the block was not successful: {}", e),
+            }
+        }
+
+        // the sealed transactions are in the hands of the other nodes now
+        let (BlockMessage::Block(block) | BlockMessage::SignedBlock { block, .. }) = message;
+        self.remove_from_wal(block.transactions, "sealed");
+    }
+
+    fn drop_expired_transactions(&mut self) {
+        if let Some(ttl) = self.transaction_ttl {
+            let now = self.clock.now();
+            let (waiting, expired): (Vec<_>, Vec<_>) = self
+                .current_transactions
+                .drain(..)
+                .partition(|(received, _)| now.saturating_duration_since(*received) <= ttl);
+            self.current_transactions = waiting;
+            if !expired.is_empty() {
+                self.expired_transactions += expired.len() as u64;
+                warn!(
+                    "BlockBuilder dropped {} expired transactions, acknowledged but never sealed (total expired: {})",
+                    expired.len(),
+                    self.expired_transactions
+                );
+                let expired: Vec<Transaction> = expired.into_iter().map(|(_, tx)| tx).collect();
+                self.remove_from_wal(expired, "expired");
             }
         }
     }
+
+    /// Take the transactions out of the WAL, so they are not proposed again after a restart. The
+    /// removal is written on a blocking thread and the block builder does not wait for it: the
+    /// transactions were logged before they were queued, so a removal cannot come before them.
+    fn remove_from_wal(&self, transactions: Vec<Transaction>, reason: &'static str) {
+        if let Some(wal) = self.wal.clone() {
+            tokio::task::spawn_blocking(move || {
+                if let Err(e) = wal.lock().unwrap().remove(&transactions) {
+                    error!("Failed to remove the {} transactions from the mempool WAL: {}", reason, e);
+                }
+            });
+        }
+    }
 }
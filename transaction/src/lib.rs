@@ -1,5 +1,7 @@
 mod block_builder;
+mod mempool_wal;
 mod transaction_coordinator;
 
 
+pub use crate::mempool_wal::MempoolWal;
 pub use crate::transaction_coordinator::TransactionCoordinator;
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use model::block::Transaction;
+
+/// The transaction was acknowledged to a client.
+const ADDED: u8 = 0;
+/// The transaction was sealed into a block or expired.
+const REMOVED: u8 = 1;
+/// Number of removals after which the log is compacted.
+const COMPACTION_THRESHOLD: usize = 1_000;
+
+/// Write-ahead log of the transactions acknowledged to the clients and neither sealed into a
+/// block nor expired yet, so that they are proposed again after a restart instead of being lost.
+/// Every entry is a kind (1 byte, added or removed), the length of the transaction (4 bytes,
+/// little endian) and its bytes. Removals are appended as well, the log is only rewritten without
+/// the removed transactions once enough of them piled up, and at recovery. The log is written
+/// through to the OS but not synced, so it survives a crash of the node but not of the machine.
+pub struct MempoolWal {
+    path: PathBuf,
+    file: File,
+    /// Removals appended since the log was last rewritten.
+    removals: usize,
+}
+
+impl MempoolWal {
+    /// Open the log at `path`, creating it if needed. The transactions already in it are kept
+    /// for `recover`.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file, removals: 0 })
+    }
+
+    /// Transactions in the log which were not removed, in the order they were accepted. An entry
+    /// cut short by a crash while it was written ends the log.
+    pub fn recover(&self) -> io::Result<Vec<Transaction>> {
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut transactions = Vec::new();
+        // positions of the transactions not removed yet, a removal takes the oldest copy out
+        let mut positions: HashMap<Transaction, VecDeque<usize>> = HashMap::new();
+        while let Some((kind, transaction)) = Self::read_entry(&mut reader)? {
+            match kind {
+                ADDED => {
+                    positions.entry(transaction.clone()).or_default().push_back(transactions.len());
+                    transactions.push(Some(transaction));
+                }
+                _ => {
+                    if let Some(position) = positions.get_mut(&transaction).and_then(|p| p.pop_front()) {
+                        transactions[position] = None;
+                    }
+                }
+            }
+        }
+        Ok(transactions.into_iter().flatten().collect())
+    }
+
+    pub fn append(&mut self, transaction: &Transaction) -> io::Result<()> {
+        self.file.write_all(&Self::entry(ADDED, transaction))
+    }
+
+    /// Take the `transactions` out of the log, once they are sealed into a block or expired. A
+    /// transaction logged more than once is only taken out as many times as it is given.
+    pub fn remove(&mut self, transactions: &[Transaction]) -> io::Result<()> {
+        let entries: Vec<u8> = transactions.iter().flat_map(|tx| Self::entry(REMOVED, tx)).collect();
+        self.file.write_all(&entries)?;
+        self.removals += transactions.len();
+        if self.removals >= COMPACTION_THRESHOLD {
+            let pending = self.recover()?;
+            self.reset(pending.iter())?;
+        }
+        Ok(())
+    }
+
+    /// Replace the content of the log with the `pending` transactions. The new content is
+    /// written aside first, so a crash leaves either the old log or the new one.
+    pub fn reset<'a>(&mut self, pending: impl Iterator<Item = &'a Transaction>) -> io::Result<()> {
+        let mut next = self.path.clone().into_os_string();
+        next.push(".next");
+        let mut file = File::create(&next)?;
+        for transaction in pending {
+            file.write_all(&Self::entry(ADDED, transaction))?;
+        }
+        file.sync_all()?;
+        fs::rename(&next, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.removals = 0;
+        Ok(())
+    }
+
+    fn read_entry(reader: &mut impl Read) -> io::Result<Option<(u8, Transaction)>> {
+        let mut header = [0u8; 5];
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut transaction = vec![0u8; u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize];
+        match reader.read_exact(&mut transaction) {
+            Ok(()) => Ok(Some((header[0], transaction))),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn entry(kind: u8, transaction: &Transaction) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(5 + transaction.len());
+        entry.push(kind);
+        entry.extend_from_slice(&(transaction.len() as u32).to_le_bytes());
+        entry.extend_from_slice(transaction);
+        entry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mempool-wal-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn removed_transactions_are_not_recovered() {
+        let path = path("removed");
+        let mut wal = MempoolWal::open(path.clone()).unwrap();
+        for tx in [vec![1], vec![2], vec![1], vec![3]] {
+            wal.append(&tx).unwrap();
+        }
+
+        wal.remove(&[vec![1], vec![3]]).unwrap();
+        wal.append(&vec![4]).unwrap();
+        let recovered = MempoolWal::open(path.clone()).unwrap().recover().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(recovered, vec![vec![2], vec![1], vec![4]]);
+    }
+
+    #[test]
+    fn interleaved_appends_and_removals_are_recovered() {
+        let path = path("interleaved");
+        let mut wal = MempoolWal::open(path.clone()).unwrap();
+        wal.append(&vec![1]).unwrap();
+        wal.append(&vec![2]).unwrap();
+        wal.remove(&[vec![1]]).unwrap();
+        // logged a second time once the first copy is gone
+        wal.append(&vec![1]).unwrap();
+        wal.append(&vec![3]).unwrap();
+        wal.remove(&[vec![2], vec![3]]).unwrap();
+        wal.append(&vec![2]).unwrap();
+        // a removal of a transaction which is not in the log changes nothing
+        wal.remove(&[vec![5]]).unwrap();
+        let recovered = MempoolWal::open(path.clone()).unwrap().recover().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(recovered, vec![vec![1], vec![2]]);
+    }
+
+    #[test]
+    fn log_is_compacted_after_enough_removals() {
+        let path = path("compacted");
+        let mut wal = MempoolWal::open(path.clone()).unwrap();
+        wal.append(&vec![0]).unwrap();
+        for i in 0..COMPACTION_THRESHOLD {
+            let tx = (i as u32 + 1).to_le_bytes().to_vec();
+            wal.append(&tx).unwrap();
+            wal.remove(&[tx]).unwrap();
+        }
+        let size = fs::metadata(&path).unwrap().len();
+        let recovered = wal.recover().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(recovered, vec![vec![0]]);
+        // only the transaction still waiting is left
+        assert_eq!(size, 6);
+    }
+}
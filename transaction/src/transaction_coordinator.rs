@@ -1,69 +1,177 @@
 use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::sink::SinkExt as _;
-use log::{debug, info, warn};
-use serde::{Deserialize, Serialize};
+use log::{debug, error, info, warn};
 use tokio::sync::mpsc::{channel, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::Semaphore;
 
-use model::block::{Block, Transaction};
+use model::block::{Block, BlockHash, BlockMessage, Transaction};
+use model::cache::BoundedSet;
+use model::clock::Clock;
 use model::committee::{Committee, Id};
-use model::DEFAULT_CHANNEL_CAPACITY;
+use model::control::ProductionSwitch;
+use model::encoding::encode_hash;
+use model::key_store::KeyStore;
+use model::parameters::Parameters;
 use network::{MessageHandler, Receiver, Writer};
+use network::error::NetworkError;
 
 use crate::block_builder::BlockBuilder;
+use crate::mempool_wal::MempoolWal;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum BlockMessage {
-    Block(Block),
-}
+/// Number of recently received block hashes remembered to recognise duplicates.
+const SEEN_BLOCKS_CAPACITY: usize = 10_000;
 
 pub struct TransactionCoordinator;
 
 impl TransactionCoordinator {
+    #[allow(clippy::too_many_arguments)]
     pub fn spawn(
         node_id: Id,
         committee: Committee,
+        parameters: Parameters,
         block_sender: Sender<Block>,
+        switch: ProductionSwitch,
+        key_store: KeyStore,
+        clock: Arc<dyn Clock>,
+        mempool_wal: Option<MempoolWal>,
     ) {
-        let (transaction_to_block_builder_sender, transaction_receiver) = channel(DEFAULT_CHANNEL_CAPACITY);
+        let (transaction_to_block_builder_sender, transaction_receiver) = channel(parameters.channel_capacities.transactions);
+        let mempool_wal = mempool_wal.map(|wal| Arc::new(Mutex::new(wal)));
 
         let tx_address = committee.get_tx_receiver_address(node_id).unwrap();
         debug!("Start listening for transactions on {:?}", tx_address);
-        Receiver::spawn(
+        Receiver::spawn_with_connection_limit(
             tx_address,
-            TxReceiverHandler { transaction_to_block_builder_sender },
+            TxReceiverHandler {
+                transaction_to_block_builder_sender,
+                busy_transactions: Arc::new(AtomicU64::new(0)),
+                invalid_transactions: Arc::new(AtomicU64::new(0)),
+                min_size: parameters.min_transaction_size,
+                exact_size: parameters.transaction_size,
+                mempool_wal: mempool_wal.clone(),
+            },
+            parameters.max_connections,
         );
 
         let address = committee.get_block_receiver_address(node_id).unwrap();
         debug!("Start listening for blocks on {:?}", address);
-        Receiver::spawn(
+        Receiver::spawn_with_connection_limit(
             address,
-            BlockReceiverHandler { block_sender },
+            BlockReceiverHandler {
+                block_sender,
+                committee: Arc::new(committee.clone()),
+                require_signature: parameters.sign_blocks,
+                verification_slots: Arc::new(Semaphore::new(parameters.verification_workers.max(1))),
+                seen_blocks: Arc::new(Mutex::new(BoundedSet::new(SEEN_BLOCKS_CAPACITY))),
+            },
+            parameters.max_connections,
         );
 
+        let signer = match parameters.sign_blocks {
+            true => Some(key_store),
+            false => None,
+        };
+
         BlockBuilder::spawn(
             transaction_receiver,
             committee,
+            parameters,
+            switch,
+            signer,
+            clock,
+            mempool_wal,
         );
     }
 }
 
+/// Hands the transactions of the clients over to the block builder. A client gets "Ack" once its
+/// transaction is queued for the block builder, which does not mean it is sealed into a block,
+/// let alone committed: it may still expire (see `Parameters::transaction_ttl`) and the client
+/// is not told. Without a mempool WAL, a transaction still waiting for a block is lost if
+/// the node stops. With one, the transaction is logged before the "Ack", so it survives a crash
+/// of the node, though not of the machine since the log is not synced. A client gets "Busy" when
+/// the queue is full or the transaction cannot be logged, and may send it again later.
 #[derive(Clone)]
 struct TxReceiverHandler {
     transaction_to_block_builder_sender: Sender<Transaction>,
+    /// Number of transactions turned away because the block builder queue was full.
+    busy_transactions: Arc<AtomicU64>,
+    /// Number of transactions rejected because of their size.
+    invalid_transactions: Arc<AtomicU64>,
+    min_size: usize,
+    exact_size: Option<usize>,
+    mempool_wal: Option<Arc<Mutex<MempoolWal>>>,
+}
+
+impl TxReceiverHandler {
+    /// Why the transaction is not accepted, if it is not.
+    fn validate(&self, transaction: &[u8]) -> Option<String> {
+        match self.exact_size {
+            Some(size) if transaction.len() != size => {
+                Some(format!("{} bytes instead of {}", transaction.len(), size))
+            }
+            _ if transaction.len() < self.min_size => {
+                Some(format!("{} bytes, less than the minimum of {}", transaction.len(), self.min_size))
+            }
+            _ => None,
+        }
+    }
+
+    /// Validate the transaction and enqueue it if it is valid. Returns the reply to the client,
+    /// "Invalid" for a rejected transaction, which is neither logged nor queued.
+    fn receive(&self, transaction: Transaction) -> &'static str {
+        if let Some(reason) = self.validate(&transaction) {
+            let invalid = self.invalid_transactions.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!("Rejected a transaction of {} (total rejected: {})", reason, invalid);
+            return "Invalid";
+        }
+        self.enqueue(transaction)
+    }
+
+    /// Log the transaction, if there is a WAL, and queue it for the block builder. Returns the
+    /// reply to the client. A slot in the queue is taken first, so a transaction is only logged
+    /// if it is queued as well.
+    fn enqueue(&self, transaction: Transaction) -> &'static str {
+        // Do not wait for free capacity, so that a saturated mempool answers the client with
+        // "Busy" instead of stalling or crashing the handler.
+        let permit = match self.transaction_to_block_builder_sender.try_reserve() {
+            Ok(permit) => permit,
+            Err(TrySendError::Full(())) => {
+                let busy = self.busy_transactions.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!("Transaction queue is full, rejected the transaction (total rejected: {})", busy);
+                return "Busy";
+            }
+            Err(TrySendError::Closed(())) => {
+                warn!("Block builder is not running, rejected the transaction");
+                return "Busy";
+            }
+        };
+        if let Some(wal) = &self.mempool_wal {
+            if let Err(e) = wal.lock().unwrap().append(&transaction) {
+                error!("Failed to log the transaction in the mempool WAL, rejected it: {}", e);
+                return "Busy";
+            }
+        }
+        permit.send(transaction);
+        "Ack"
+    }
 }
 
 #[async_trait]
 impl MessageHandler for TxReceiverHandler {
-    async fn dispatch(&self, _writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
+    async fn dispatch(&self, writer: &mut Writer, message: Bytes) -> Result<(), Box<dyn Error>> {
         info!("TxReceiverHandler received transaction to process {:?}", message);
-        // Send the transaction to the block builder.
-        self.transaction_to_block_builder_sender
-            .send(message.to_vec())
-            .await
-            .expect("Failed to send transaction");
+        // the transaction is written to the WAL on a blocking thread
+        let handler = self.clone();
+        let transaction = message.to_vec();
+        let reply = tokio::task::spawn_blocking(move || handler.receive(transaction)).await?;
+        let _ = writer.send(Bytes::from(reply)).await;
 
         Ok(())
     }
@@ -72,16 +180,81 @@ impl MessageHandler for TxReceiverHandler {
 #[derive(Clone)]
 struct BlockReceiverHandler {
     block_sender: Sender<Block>,
+    committee: Arc<Committee>,
+    /// Reject blocks which are not signed.
+    require_signature: bool,
+    /// Bounds the number of blocks verified at the same time, each on a blocking thread so that
+    /// the verification of the blocks of different peers runs in parallel.
+    verification_slots: Arc<Semaphore>,
+    /// Recently received blocks, to not hand the same block to consensus twice.
+    seen_blocks: Arc<Mutex<BoundedSet<BlockHash>>>,
+}
+
+impl BlockReceiverHandler {
+    /// The block if it is authentic and was not received before. A block is only remembered once
+    /// it is authenticated, which checks its transactions against its hash, so a tampered copy
+    /// received first is rejected and does not shadow the genuine block.
+    fn accept(&self, message: BlockMessage) -> Option<Block> {
+        let block = Self::authenticate(&self.committee, self.require_signature, message)?;
+        if !self.seen_blocks.lock().unwrap().insert(block.hash()) {
+            debug!("Block {} was already received", encode_hash(block.hash()));
+            return None;
+        }
+        Some(block)
+    }
+
+    /// The block if it can be accepted. Blocks whose transactions do not match their hash, or
+    /// with an unknown author or a bad signature are not: the signature only covers the hash, so
+    /// without checking the hash first, a genuine hash and signature could come with any
+    /// transactions.
+    fn authenticate(committee: &Committee, require_signature: bool, message: BlockMessage) -> Option<Block> {
+        let (BlockMessage::Block(block) | BlockMessage::SignedBlock { block, .. }) = &message;
+        if let Err(e) = block.verify() {
+            warn!("Rejected block: {}", e);
+            return None;
+        }
+        match message {
+            BlockMessage::Block(block) if require_signature => {
+                warn!("Rejected unsigned block {}", encode_hash(block.hash()));
+                None
+            }
+            BlockMessage::Block(block) => Some(block),
+            BlockMessage::SignedBlock { author, block, signature } => {
+                let key = match committee.get_verification_key(&author) {
+                    Some(key) => key,
+                    None => {
+                        warn!("Rejected block {} from an unknown author", encode_hash(block.hash()));
+                        return None;
+                    }
+                };
+                match block.verify_signature(&key, &signature) {
+                    Ok(()) => Some(block),
+                    Err(e) => {
+                        warn!("Rejected block: {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl MessageHandler for BlockReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        // debug!("BlockReceiverHandler received the message and sends back the response");
-        let _ = writer.send(Bytes::from("Ack")).await;
+        // the peer sends the block again if it does not get the ACK, it is processed then
+        writer.send(Bytes::from("Ack")).await.map_err(NetworkError::FailedToSendAck)?;
 
         match bincode::deserialize(&serialized) {
-            Ok(BlockMessage::Block(block)) => {
+            Ok(message) => {
+                // a peer's blocks are still handed over in order, since its next message is only
+                // dispatched once this one is done
+                let _slot = self.verification_slots.acquire().await?;
+                let handler = self.clone();
+                let block = match tokio::task::spawn_blocking(move || handler.accept(message)).await? {
+                    Some(block) => block,
+                    None => return Ok(()),
+                };
                 info!("BlockReceiverHandler received block to process with {} transactions and sends it to Consensus", block.transactions.len());
                 self
                     .block_sender
@@ -93,4 +266,150 @@ impl MessageHandler for BlockReceiverHandler {
         }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use model::key_store::KeyStore;
+
+    use super::*;
+
+    fn signed(block: Block, signer: &Block, key_store: &KeyStore) -> BlockMessage {
+        BlockMessage::SignedBlock {
+            author: key_store.public_key(),
+            signature: signer.sign(key_store),
+            block,
+        }
+    }
+
+    #[test]
+    fn signed_block_is_accepted() {
+        let key_store = KeyStore::default_for(1).unwrap();
+        let block = Block::new(vec![vec![0, 1]]);
+        let message = signed(block.clone(), &block, &key_store);
+
+        let accepted = BlockReceiverHandler::authenticate(&Committee::default(), true, message);
+        assert_eq!(accepted.map(|block| block.hash()), Some(block.hash()));
+    }
+
+    #[test]
+    fn replayed_signature_with_other_transactions_is_rejected() {
+        let key_store = KeyStore::default_for(1).unwrap();
+        let genuine = Block::new(vec![vec![0, 1]]);
+        // the genuine hash and signature, with other transactions
+        let tampered = Block { hash: genuine.hash(), transactions: vec![vec![0, 2]] };
+        let message = signed(tampered, &genuine, &key_store);
+
+        assert!(BlockReceiverHandler::authenticate(&Committee::default(), true, message).is_none());
+    }
+
+    #[test]
+    fn tampered_copy_received_first_does_not_shadow_the_block() {
+        let key_store = KeyStore::default_for(1).unwrap();
+        let (block_sender, _block_receiver) = channel(1);
+        let handler = BlockReceiverHandler {
+            block_sender,
+            committee: Arc::new(Committee::default()),
+            require_signature: true,
+            verification_slots: Arc::new(Semaphore::new(1)),
+            seen_blocks: Arc::new(Mutex::new(BoundedSet::new(SEEN_BLOCKS_CAPACITY))),
+        };
+        let genuine = Block::new(vec![vec![0, 1]]);
+        let tampered = Block { hash: genuine.hash(), transactions: vec![vec![0, 2]] };
+
+        assert!(handler.accept(signed(tampered, &genuine, &key_store)).is_none());
+        let accepted = handler.accept(signed(genuine.clone(), &genuine, &key_store));
+        assert_eq!(accepted.map(|block| block.transactions), Some(genuine.transactions.clone()));
+        // the genuine block is remembered now
+        assert!(handler.accept(signed(genuine.clone(), &genuine, &key_store)).is_none());
+    }
+
+    fn wal_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("tx-receiver-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn tx_handler(
+        min_size: usize,
+        exact_size: Option<usize>,
+        queue_size: usize,
+        wal: &Arc<Mutex<MempoolWal>>,
+    ) -> (TxReceiverHandler, tokio::sync::mpsc::Receiver<Transaction>) {
+        let (transaction_to_block_builder_sender, transaction_receiver) = channel(queue_size);
+        let handler = TxReceiverHandler {
+            transaction_to_block_builder_sender,
+            busy_transactions: Arc::new(AtomicU64::new(0)),
+            invalid_transactions: Arc::new(AtomicU64::new(0)),
+            min_size,
+            exact_size,
+            mempool_wal: Some(wal.clone()),
+        };
+        (handler, transaction_receiver)
+    }
+
+    #[test]
+    fn transaction_is_logged_before_it_is_acknowledged() {
+        let path = wal_path("logged");
+        let wal = Arc::new(Mutex::new(MempoolWal::open(path.clone()).unwrap()));
+        let (handler, mut transaction_receiver) = tx_handler(0, None, 1, &wal);
+
+        assert_eq!(handler.enqueue(vec![1]), "Ack");
+        // the queue is full, the transaction is neither queued nor logged
+        assert_eq!(handler.enqueue(vec![2]), "Busy");
+        let recovered = wal.lock().unwrap().recover().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(recovered, vec![vec![1]]);
+        assert_eq!(transaction_receiver.try_recv().unwrap(), vec![1]);
+        assert!(transaction_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn transaction_below_the_minimum_size_is_rejected() {
+        let path = wal_path("minimum");
+        let wal = Arc::new(Mutex::new(MempoolWal::open(path.clone()).unwrap()));
+        let (handler, mut transaction_receiver) = tx_handler(9, None, 10, &wal);
+
+        assert_eq!(handler.receive(vec![]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 8]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 9]), "Ack");
+        assert_eq!(handler.receive(vec![1; 100]), "Ack");
+        let recovered = wal.lock().unwrap().recover().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(handler.invalid_transactions.load(Ordering::Relaxed), 2);
+        assert_eq!(recovered, vec![vec![0; 9], vec![1; 100]]);
+        assert_eq!(transaction_receiver.try_recv().unwrap(), vec![0; 9]);
+        assert_eq!(transaction_receiver.try_recv().unwrap(), vec![1; 100]);
+        assert!(transaction_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn transaction_of_another_size_than_the_fixed_one_is_rejected() {
+        let path = wal_path("fixed");
+        let wal = Arc::new(Mutex::new(MempoolWal::open(path.clone()).unwrap()));
+        let (handler, mut transaction_receiver) = tx_handler(9, Some(16), 10, &wal);
+
+        assert_eq!(handler.receive(vec![]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 9]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 15]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 17]), "Invalid");
+        assert_eq!(handler.receive(vec![0; 16]), "Ack");
+        let recovered = wal.lock().unwrap().recover().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(handler.invalid_transactions.load(Ordering::Relaxed), 4);
+        assert_eq!(recovered, vec![vec![0; 16]]);
+        assert_eq!(transaction_receiver.try_recv().unwrap(), vec![0; 16]);
+        assert!(transaction_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn unsigned_block_not_matching_its_hash_is_rejected() {
+        let genuine = Block::new(vec![vec![0, 1]]);
+        let tampered = Block { hash: genuine.hash(), transactions: vec![vec![0, 2]] };
+
+        assert!(BlockReceiverHandler::authenticate(&Committee::default(), false, BlockMessage::Block(tampered)).is_none());
+    }
+}
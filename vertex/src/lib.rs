@@ -1,4 +1,6 @@
 #[macro_use]
 pub mod vertex_coordinator;
 pub mod vertex_broadcaster;
+pub mod vertex_gossiper;
+pub mod vertex_message;
 mod vertex_message_handler;
@@ -1,43 +1,123 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+
 use bytes::Bytes;
-use log::{debug, error};
+use futures::stream::{FuturesUnordered, StreamExt as _};
+use log::{debug, error, warn};
 use tokio::sync::mpsc::{Receiver};
+use tokio::time::{sleep, Duration};
 
 use model::committee::Committee;
 use model::vertex::{Vertex};
-use network::ReliableSender;
+use network::{CancelHandler, ReliableSender};
+use network::error::NetworkError;
+
+use crate::vertex_message::VertexMessage;
 
 pub struct VertexBroadcaster {
     vertex_to_broadcast_receiver: Receiver<Vertex>,
     network: ReliableSender,
-    committee: Committee
+    committee: Committee,
+    max_message_size: usize,
+    /// Time to wait for a quorum of acknowledgements before the vertex is broadcast again.
+    rebroadcast_delay: Duration,
 }
 
 impl VertexBroadcaster {
-    pub fn spawn(vertex_to_broadcast_receiver: Receiver<Vertex>, network: ReliableSender, committee: Committee) {
+    pub fn spawn(
+        vertex_to_broadcast_receiver: Receiver<Vertex>,
+        network: ReliableSender,
+        committee: Committee,
+        max_message_size: usize,
+        rebroadcast_delay: Duration,
+    ) {
         tokio::spawn(async move {
-            Self { vertex_to_broadcast_receiver, network, committee}.run().await;
+            Self { vertex_to_broadcast_receiver, network, committee, max_message_size, rebroadcast_delay }.run().await;
         });
     }
 
     pub async fn run(&mut self) {
         loop {
-            match self.vertex_to_broadcast_receiver.recv().await.unwrap() {
-                vertex => {
-                    debug!("Vertex received for broadcast {}", vertex);
-                    let addresses = self
-                        .committee
-                        .get_node_addresses();
-                    let bytes = bincode::serialize(&vertex).expect("Failed to serialize vertex in VertexBroadcaster");
-
-                    let handlers = self.network.broadcast(addresses, Bytes::from(bytes)).await;
-                    for h in handlers {
-                        if let Err(e) = h.await {
-                            error!("Broadcast of vertices was not successful")
+            let vertex = self.vertex_to_broadcast_receiver.recv().await.unwrap();
+            debug!("Vertex received for broadcast {}", vertex);
+            self.broadcast(&vertex).await;
+        }
+    }
+
+    /// Broadcast the vertex and wait until a quorum of nodes acknowledged all of its messages.
+    /// Whenever the quorum is not reached within `rebroadcast_delay`, the same messages are sent
+    /// again to the nodes which did not acknowledge them yet, replacing the previous ones, so a
+    /// lost broadcast does not cost the node its vertex in the round. The vertex is not rebuilt,
+    /// and the nodes which already got it recognise it by its hash. Once the quorum is reached,
+    /// the delivery to the remaining nodes goes on in the background.
+    async fn broadcast(&mut self, vertex: &Vertex) {
+        let messages = VertexMessage::serialize(vertex, self.max_message_size);
+        let addresses = self.committee.get_node_addresses();
+        let quorum = self.committee.quorum_threshold();
+        let mut delivered = HashSet::new();
+        loop {
+            let mut deliveries = FuturesUnordered::new();
+            for address in addresses.iter().filter(|a| !delivered.contains(*a)) {
+                match self.send(*address, &messages).await {
+                    Ok(handlers) => deliveries.push(Self::deliver(*address, handlers)),
+                    // the node gets the vertex with the next broadcast
+                    Err(e) => warn!("Failed to broadcast the vertex {}: {}", vertex, e),
+                }
+            }
+
+            let timer = sleep(self.rebroadcast_delay);
+            tokio::pin!(timer);
+            loop {
+                tokio::select! {
+                    Some((address, success)) = deliveries.next() => {
+                        if success {
+                            delivered.insert(address);
                         }
-                    }
+                        if delivered.len() >= quorum {
+                            Self::deliver_remaining(deliveries);
+                            return;
+                        }
+                    },
+                    () = &mut timer => break,
                 }
             }
+            warn!(
+                "Vertex {} reached {} of {} nodes, broadcast it again to the others",
+                vertex,
+                delivered.len(),
+                addresses.len()
+            );
+        }
+    }
+
+    async fn send(&mut self, address: SocketAddr, messages: &[Bytes]) -> Result<Vec<CancelHandler>, NetworkError> {
+        let mut handlers = Vec::new();
+        for bytes in messages {
+            handlers.push(self.network.send(address, bytes.clone()).await?);
+        }
+        Ok(handlers)
+    }
+
+    /// Whether all the messages to the node were acknowledged.
+    async fn deliver(address: SocketAddr, handlers: Vec<CancelHandler>) -> (SocketAddr, bool) {
+        for h in handlers {
+            if h.await.is_err() {
+                return (address, false);
+            }
         }
+        (address, true)
     }
 
+    fn deliver_remaining<F>(mut deliveries: FuturesUnordered<F>)
+    where
+        F: std::future::Future<Output = (SocketAddr, bool)> + Send + 'static,
+    {
+        tokio::spawn(async move {
+            while let Some((address, success)) = deliveries.next().await {
+                if !success {
+                    error!("Broadcast of vertices to {} was not successful", address)
+                }
+            }
+        });
+    }
 }
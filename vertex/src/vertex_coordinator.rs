@@ -1,13 +1,26 @@
+use std::sync::{Arc, Mutex};
+
 use log::{debug, info};
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::Duration;
 
+use model::cache::BoundedSet;
 use model::committee::{Committee, Id};
+use model::parameters::Parameters;
 use model::vertex::{Vertex};
-use network::{Receiver as NetworkReceiver, ReliableSender};
+use network::{Receiver as NetworkReceiver, ReliableSender, RetryBudget};
 
 use crate::vertex_broadcaster::VertexBroadcaster;
+use crate::vertex_gossiper::VertexGossiper;
+use crate::vertex_message::ChunkAssembler;
 use crate::vertex_message_handler::VertexReceiverHandler;
 
+/// Number of recently received vertex hashes remembered to recognise duplicates.
+const SEEN_VERTICES_CAPACITY: usize = 10_000;
+
+/// Number of chunked vertices which can be assembled at the same time.
+const PARTIAL_VERTICES_CAPACITY: usize = 100;
+
 pub struct VertexCoordinator;
 
 impl VertexCoordinator {
@@ -15,21 +28,41 @@ impl VertexCoordinator {
         node_id: Id,
         committee: Committee,
         vertex_to_consensus_sender: Sender<Vertex>,
-        vertex_to_broadcast_receiver: Receiver<Vertex>
+        vertex_to_broadcast_receiver: Receiver<Vertex>,
+        parameters: Parameters,
     ) {
+        let vertex_to_gossip_sender = if parameters.gossip_fanout > 0 {
+            let (sender, receiver) = channel(parameters.channel_capacities.vertices);
+            VertexGossiper::spawn(node_id, committee.clone(), parameters.gossip_fanout, parameters.max_vertex_message_size, receiver);
+            Some(sender)
+        } else {
+            None
+        };
+
         // Spawn the network receiver listening to vertices broadcasted from the other nodes.
         debug!("Start listening for vertices from other nodes");
         let address = committee.get_node_address(node_id).unwrap();
-        NetworkReceiver::spawn(
+        NetworkReceiver::spawn_with_connection_limit(
             address,
-            VertexReceiverHandler { vertex_to_consensus_sender },
+            VertexReceiverHandler {
+                vertex_to_consensus_sender,
+                vertex_to_gossip_sender,
+                seen_vertices: Arc::new(Mutex::new(BoundedSet::new(SEEN_VERTICES_CAPACITY))),
+                chunks: Arc::new(Mutex::new(ChunkAssembler::new(PARTIAL_VERTICES_CAPACITY, parameters.max_vertex_message_size))),
+            },
+            parameters.max_connections,
         );
         info!("Vertex Coordinator listening to the messages on {}", address);
 
         VertexBroadcaster::spawn(
             vertex_to_broadcast_receiver,
-            ReliableSender::new(),
-            committee
+            ReliableSender::new().with_retry_budget(RetryBudget {
+                max_retries: parameters.message_max_retries,
+                max_time: parameters.message_max_time.map(Duration::from_millis),
+            }),
+            committee,
+            parameters.max_vertex_message_size,
+            Duration::from_millis(parameters.vertex_rebroadcast_delay),
         );
     }
 }
\ No newline at end of file
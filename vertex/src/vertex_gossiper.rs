@@ -0,0 +1,81 @@
+use log::{debug, warn};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval, Duration};
+
+use model::committee::{Committee, Id};
+use model::vertex::Vertex;
+use network::SimpleSender;
+
+use crate::vertex_message::VertexMessage;
+
+/// How often the messages dropped because of slow peers are reported.
+const DROPPED_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Forwards vertices received from other nodes to a few random peers, so that a vertex still
+/// reaches nodes which have no working link to its owner.
+pub struct VertexGossiper {
+    node_id: Id,
+    committee: Committee,
+    fanout: usize,
+    max_message_size: usize,
+    vertex_to_gossip_receiver: Receiver<Vertex>,
+    network: SimpleSender,
+    /// Dropped messages already reported.
+    reported_dropped: u64,
+}
+
+impl VertexGossiper {
+    pub fn spawn(node_id: Id, committee: Committee, fanout: usize, max_message_size: usize, vertex_to_gossip_receiver: Receiver<Vertex>) {
+        tokio::spawn(async move {
+            Self {
+                node_id,
+                committee,
+                fanout,
+                max_message_size,
+                vertex_to_gossip_receiver,
+                network: SimpleSender::new(),
+                reported_dropped: 0,
+            }
+            .run()
+            .await;
+        });
+    }
+
+    async fn run(&mut self) {
+        let mut report = interval(DROPPED_REPORT_INTERVAL);
+        loop {
+            tokio::select! {
+                vertex = self.vertex_to_gossip_receiver.recv() => match vertex {
+                    Some(vertex) => self.gossip(vertex).await,
+                    None => return,
+                },
+                _ = report.tick() => self.report_dropped(),
+            }
+        }
+    }
+
+    async fn gossip(&mut self, vertex: Vertex) {
+        debug!("Gossip vertex {} to {} peers", vertex, self.fanout);
+        // all the chunks of a vertex go to the same peers
+        let addresses = self.committee.get_node_addresses_but_me(self.node_id);
+        let addresses = self.network.lucky_pick(addresses, self.fanout);
+        for bytes in VertexMessage::serialize(&vertex, self.max_message_size) {
+            self.network.broadcast(addresses.clone(), bytes).await;
+        }
+    }
+
+    /// Report the messages dropped since the last report, if any. Gossip is best effort, but a
+    /// growing count tells that some peers cannot keep up.
+    fn report_dropped(&mut self) {
+        let dropped = self.network.dropped();
+        if dropped > self.reported_dropped {
+            warn!(
+                "Gossip dropped {} messages to slow peers in the last {}s (total dropped: {})",
+                dropped - self.reported_dropped,
+                DROPPED_REPORT_INTERVAL.as_secs(),
+                dropped
+            );
+            self.reported_dropped = dropped;
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+use bytes::Bytes;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use model::encoding::encode_hash;
+use model::vertex::{Vertex, VertexHash};
+
+/// Largest number of chunks a vertex may be split in.
+const MAX_CHUNKS: u32 = 1_024;
+
+#[derive(Serialize, Deserialize)]
+pub enum VertexMessage {
+    Vertex(Vertex),
+    /// Part of a vertex too large to be sent in a single message.
+    Chunk(VertexChunk),
+}
+
+/// Every chunk tells which vertex it belongs to, so that chunks can be put together whatever
+/// connection they come from.
+#[derive(Serialize, Deserialize)]
+pub struct VertexChunk {
+    pub vertex: VertexHash,
+    pub index: u32,
+    pub count: u32,
+    pub data: Vec<u8>,
+}
+
+impl VertexMessage {
+    /// Serialized messages carrying the vertex. A vertex larger than `max_size` bytes is split in
+    /// chunks of at most `max_size` bytes of data.
+    pub fn serialize(vertex: &Vertex, max_size: usize) -> Vec<Bytes> {
+        let encoded = bincode::serialize(vertex).expect("Failed to serialize vertex");
+        if encoded.len() <= max_size {
+            let message = VertexMessage::Vertex(vertex.clone());
+            return vec![Bytes::from(bincode::serialize(&message).expect("Failed to serialize vertex"))];
+        }
+
+        let chunks = encoded.chunks(max_size.max(1));
+        let count = chunks.len() as u32;
+        (0..)
+            .zip(chunks)
+            .map(|(index, data)| {
+                let message = VertexMessage::Chunk(VertexChunk {
+                    vertex: vertex.hash(),
+                    index,
+                    count,
+                    data: data.to_vec(),
+                });
+                Bytes::from(bincode::serialize(&message).expect("Failed to serialize vertex chunk"))
+            })
+            .collect()
+    }
+}
+
+/// Puts chunked vertices back together. At most `capacity` vertices are assembled at the same
+/// time, the oldest incomplete one is dropped to make room for a new one. Chunks carry no more
+/// than `max_chunk_size` bytes of data, the `max_size` the vertices are split with, so a vertex
+/// cannot take more than `MAX_CHUNKS` times that much memory.
+pub struct ChunkAssembler {
+    capacity: usize,
+    max_chunk_size: usize,
+    partial: HashMap<VertexHash, (u32, BTreeMap<u32, Vec<u8>>)>,
+    order: VecDeque<VertexHash>,
+}
+
+impl ChunkAssembler {
+    pub fn new(capacity: usize, max_chunk_size: usize) -> Self {
+        Self {
+            capacity,
+            max_chunk_size,
+            partial: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Add the chunk and return the vertex once all its chunks are there. The vertex is only
+    /// returned if it matches the hash its chunks were sent for.
+    pub fn add(&mut self, chunk: VertexChunk) -> Option<model::Result<Vertex>> {
+        if chunk.count == 0 || chunk.count > MAX_CHUNKS || chunk.index >= chunk.count {
+            return Some(Err(model::Error::UnexpectedError(format!("Invalid chunk {} of {}", chunk.index, chunk.count))));
+        }
+        if chunk.data.len() > self.max_chunk_size {
+            return Some(Err(model::Error::UnexpectedError(format!(
+                "Chunk of {} bytes is larger than {} bytes",
+                chunk.data.len(),
+                self.max_chunk_size
+            ))));
+        }
+
+        if !self.partial.contains_key(&chunk.vertex) {
+            if self.order.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.partial.remove(&oldest);
+                }
+            }
+            self.order.push_back(chunk.vertex);
+        }
+        let (count, chunks) = self.partial.entry(chunk.vertex).or_insert_with(|| (chunk.count, BTreeMap::new()));
+        if *count != chunk.count {
+            // one of the senders lies about the vertex, the chunks gathered so far cannot be told
+            // apart from its chunks, so the vertex starts over from this chunk
+            warn!(
+                "Chunk count of the vertex {} changed from {} to {}, dropped its {} chunks",
+                encode_hash(chunk.vertex), count, chunk.count, chunks.len()
+            );
+            *count = chunk.count;
+            chunks.clear();
+        }
+        chunks.insert(chunk.index, chunk.data);
+        if chunks.len() < *count as usize {
+            return None;
+        }
+
+        let (_, chunks) = self.partial.remove(&chunk.vertex)?;
+        self.order.retain(|hash| *hash != chunk.vertex);
+        let encoded: Vec<u8> = chunks.into_values().flatten().collect();
+        let vertex: Vertex = match bincode::deserialize(&encoded) {
+            Ok(vertex) => vertex,
+            Err(e) => return Some(Err(model::Error::SerializationError(e))),
+        };
+        if vertex.hash() != chunk.vertex {
+            return Some(Err(model::Error::CorruptedVertex(encode_hash(chunk.vertex))));
+        }
+        // the hash has to match the content as well, not only the hash the chunks were sent for
+        Some(vertex.verify().map(|()| vertex))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use model::block::Block;
+    use model::committee::Committee;
+    use model::vertex::Vertex;
+
+    use super::{ChunkAssembler, VertexChunk, VertexMessage};
+
+    const MAX_SIZE: usize = 100;
+
+    fn large_vertex() -> Vertex {
+        let owner = Committee::default().get_nodes_keys()[0];
+        Vertex::new(owner, 2, Block::new(vec![vec![b'a'; 10 * MAX_SIZE]]), BTreeMap::new())
+    }
+
+    fn chunks(vertex: &Vertex) -> Vec<VertexChunk> {
+        VertexMessage::serialize(vertex, MAX_SIZE)
+            .into_iter()
+            .map(|bytes| match bincode::deserialize(&bytes).unwrap() {
+                VertexMessage::Chunk(chunk) => chunk,
+                VertexMessage::Vertex(_) => panic!("Vertex was not split"),
+            })
+            .collect()
+    }
+
+    fn assemble(assembler: &mut ChunkAssembler, chunks: Vec<VertexChunk>) -> Option<model::Result<Vertex>> {
+        chunks.into_iter().filter_map(|chunk| assembler.add(chunk)).next()
+    }
+
+    #[test]
+    fn chunks_are_put_back_together_in_any_order() {
+        let vertex = large_vertex();
+        let mut chunks = chunks(&vertex);
+        assert!(chunks.len() > 1);
+        chunks.reverse();
+
+        let mut assembler = ChunkAssembler::new(10, MAX_SIZE);
+        assert_eq!(assemble(&mut assembler, chunks).unwrap().unwrap().hash(), vertex.hash());
+    }
+
+    #[test]
+    fn chunk_larger_than_the_max_size_is_rejected() {
+        let vertex = large_vertex();
+        let mut chunk = chunks(&vertex).remove(0);
+        chunk.data.resize(MAX_SIZE + 1, 0);
+
+        let mut assembler = ChunkAssembler::new(10, MAX_SIZE);
+        assert!(assembler.add(chunk).unwrap().is_err());
+    }
+
+    #[test]
+    fn forged_chunk_count_does_not_block_the_vertex() {
+        let vertex = large_vertex();
+        let chunks = chunks(&vertex);
+        let forged = VertexChunk { vertex: vertex.hash(), index: 0, count: 2, data: vec![0; MAX_SIZE] };
+
+        let mut assembler = ChunkAssembler::new(10, MAX_SIZE);
+        assert!(assembler.add(forged).is_none());
+        assert_eq!(assemble(&mut assembler, chunks).unwrap().unwrap().hash(), vertex.hash());
+    }
+
+    #[test]
+    fn tampered_chunk_is_rejected() {
+        let vertex = large_vertex();
+        let mut chunks = chunks(&vertex);
+        let middle = chunks.len() / 2;
+        let byte = chunks[middle].data.iter().position(|b| *b == b'a').unwrap();
+        chunks[middle].data[byte] = b'b';
+
+        let mut assembler = ChunkAssembler::new(10, MAX_SIZE);
+        assert!(assemble(&mut assembler, chunks).unwrap().is_err());
+    }
+}
@@ -1,30 +1,112 @@
 use std::error::Error;
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::SinkExt;
+use log::debug;
 use tokio::sync::mpsc::{Sender};
 
-use model::vertex::{Vertex};
+use model::cache::BoundedSet;
+use model::vertex::{Vertex, VertexHash};
 use network::{MessageHandler, Writer};
+use network::error::NetworkError;
+
+use crate::vertex_message::{ChunkAssembler, VertexMessage};
 
 #[derive(Clone)]
 pub struct VertexReceiverHandler {
     pub vertex_to_consensus_sender: Sender<Vertex>,
+    /// Vertices received for the first time are forwarded here to be gossiped, if gossip is enabled.
+    pub vertex_to_gossip_sender: Option<Sender<Vertex>>,
+    /// Recently received vertices, to not process (and gossip) the same vertex twice.
+    pub seen_vertices: Arc<Mutex<BoundedSet<VertexHash>>>,
+    /// Chunks of the vertices too large for a single message, until all of them are received.
+    pub chunks: Arc<Mutex<ChunkAssembler>>,
+}
+
+impl VertexReceiverHandler {
+    /// The vertex of the message once it is complete, valid and was not received before. Invalid
+    /// vertices, such as vertices with a parent which is not in an older round, are rejected
+    /// here and never reach consensus.
+    fn accept(&self, message: VertexMessage) -> Result<Option<Vertex>, model::Error> {
+        let vertex = match message {
+            VertexMessage::Vertex(vertex) => vertex,
+            VertexMessage::Chunk(chunk) => match self.chunks.lock().unwrap().add(chunk) {
+                Some(vertex) => vertex?,
+                None => return Ok(None),
+            },
+        };
+        vertex.verify()?;
+
+        if !self.seen_vertices.lock().unwrap().insert(vertex.hash()) {
+            debug!("Vertex {} was already received", vertex);
+            return Ok(None);
+        }
+        Ok(Some(vertex))
+    }
 }
 
 #[async_trait]
 impl MessageHandler for VertexReceiverHandler {
     async fn dispatch(&self, writer: &mut Writer, serialized: Bytes) -> Result<(), Box<dyn Error>> {
-        let _ = writer.send(Bytes::from("Ack")).await;
-
-        match bincode::deserialize(&serialized).map_err(model::Error::SerializationError)? {
-            vertex => self
-                .vertex_to_consensus_sender
-                .send(vertex)
-                .await
-                .expect("Failed to send vertex to consensus"),
+        // the peer sends the vertex again if it does not get the ACK, it is processed then
+        writer.send(Bytes::from("Ack")).await.map_err(NetworkError::FailedToSendAck)?;
+
+        let message: VertexMessage = bincode::deserialize(&serialized).map_err(model::Error::SerializationError)?;
+        let vertex = match self.accept(message)? {
+            Some(vertex) => vertex,
+            None => return Ok(()),
+        };
+
+        if let Some(gossip_sender) = &self.vertex_to_gossip_sender {
+            // gossip is best effort, never hold up the delivery to consensus because of it
+            let _ = gossip_sender.try_send(vertex.clone());
         }
+
+        self.vertex_to_consensus_sender
+            .send(vertex)
+            .await
+            .expect("Failed to send vertex to consensus");
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use model::block::Block;
+    use model::committee::Committee;
+    use tokio::sync::mpsc::channel;
+
+    use super::*;
+
+    fn handler() -> VertexReceiverHandler {
+        let (vertex_to_consensus_sender, _) = channel(1);
+        VertexReceiverHandler {
+            vertex_to_consensus_sender,
+            vertex_to_gossip_sender: None,
+            seen_vertices: Arc::new(Mutex::new(BoundedSet::new(10))),
+            chunks: Arc::new(Mutex::new(ChunkAssembler::new(10, 100))),
+        }
+    }
+
+    #[test]
+    fn vertex_with_a_parent_not_in_an_older_round_is_rejected() {
+        let handler = handler();
+        let owner = Committee::default().get_nodes_keys()[0];
+        for parent_round in [3, 4] {
+            let parents = BTreeMap::from([([2; 32], 2), ([3; 32], parent_round)]);
+            let vertex = Vertex::new(owner, 3, Block::default(), parents);
+
+            assert!(handler.accept(VertexMessage::Vertex(vertex.clone())).is_err(), "parent in the round {}", parent_round);
+            assert!(!handler.seen_vertices.lock().unwrap().contains(&vertex.hash()));
+        }
+
+        let vertex = Vertex::new(owner, 3, Block::default(), BTreeMap::from([([2; 32], 2)]));
+        assert_eq!(handler.accept(VertexMessage::Vertex(vertex.clone())).unwrap(), Some(vertex.clone()));
+        // already received
+        assert_eq!(handler.accept(VertexMessage::Vertex(vertex)).unwrap(), None);
+    }
+}